@@ -2,16 +2,26 @@ use anyhow::{anyhow, Context, Result};
 use itertools::Itertools;
 use nom::{
     branch::{alt, permutation},
-    bytes::complete::{tag, take_till, take_until},
-    character::complete::{alpha1, anychar, char, digit1, hex_digit1, multispace0, space1},
-    combinator::{map, map_res, opt, verify},
+    bytes::complete::{tag, take_till, take_till1, take_until},
+    character::complete::{alpha1, anychar, char, digit1, hex_digit1, multispace0, space0, space1},
+    combinator::{all_consuming, map, map_res, opt, recognize, verify},
     multi::{count, separated_list1},
     sequence::{delimited, pair, preceded, separated_pair, terminated},
     IResult,
 };
 
-use std::collections::{HashMap, HashSet};
-use std::ops::RangeInclusive;
+use num_bigint::BigUint;
+use rand::{
+    distributions::{Distribution, WeightedIndex},
+    rngs::StdRng,
+    Rng, SeedableRng,
+};
+
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::convert::TryFrom;
+use std::io::{BufRead, Write};
+use std::ops::{Range, RangeInclusive};
 
 fn find_product_of_entries_with_sum(entries: &[i32], num_entries: usize, sum: i32) -> Result<i32> {
     for combination in entries.iter().combinations(num_entries) {
@@ -359,19 +369,134 @@ fn seat_id(row: i32, column: i32) -> i32 {
     row * 8 + column
 }
 
-fn day_5() -> Result<()> {
-    let input = std::fs::read_to_string("res/day_5_input")?;
-
-    let passes = input
+fn parse_boarding_passes(input: &str) -> Result<Vec<(i32, i32)>> {
+    input
         .lines()
         .map(|line| {
             Ok(parse_seat(line)
                 .map_err(|err| anyhow!("Error parsing seats: {:?}", err))?
                 .1)
         })
-        .collect::<Result<Vec<_>>>()?;
+        .collect::<Result<Vec<_>>>()
+}
+
+const CABIN_ROWS: i32 = 128;
+const CABIN_COLUMNS: i32 = 8;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum CabinSeat {
+    Occupied,
+    Free,
+    // Seats before the first or after the last boarding pass, which don't exist on this plane
+    Missing,
+}
+
+fn cabin_seats(pass_ids: &[i32]) -> Vec<CabinSeat> {
+    let min_id = pass_ids.iter().min().copied().unwrap_or(0);
+    let max_id = pass_ids.iter().max().copied().unwrap_or(-1);
+    let mut seats = (0..CABIN_ROWS * CABIN_COLUMNS)
+        .map(|id| {
+            if id < min_id || id > max_id {
+                CabinSeat::Missing
+            } else {
+                CabinSeat::Free
+            }
+        })
+        .collect::<Vec<_>>();
+
+    for id in pass_ids {
+        seats[*id as usize] = CabinSeat::Occupied;
+    }
+
+    seats
+}
+
+fn render_seat_map(seats: &[CabinSeat]) -> String {
+    seats
+        .chunks(CABIN_COLUMNS as usize)
+        .enumerate()
+        .map(|(row, seats)| {
+            let seats = seats
+                .iter()
+                .map(|seat| match seat {
+                    CabinSeat::Occupied => '#',
+                    CabinSeat::Free => '.',
+                    CabinSeat::Missing => ' ',
+                })
+                .collect::<String>();
+            format!("{:3} |{}|\n", row, seats)
+        })
+        .collect()
+}
+
+fn free_seat_runs(seats: &[CabinSeat]) -> Vec<(CabinSeat, RangeInclusive<i32>)> {
+    seats
+        .iter()
+        .enumerate()
+        .group_by(|(_id, seat)| **seat)
+        .into_iter()
+        .filter(|(seat, _group)| *seat != CabinSeat::Occupied)
+        .map(|(seat, mut group)| {
+            let first = group.next().unwrap().0 as i32;
+            let last = group.last().map_or(first, |(id, _)| id as i32);
+            (seat, first..=last)
+        })
+        .collect()
+}
+
+// Finds the single free seat ID between the lowest and highest occupied IDs, in time
+// linear to the number of passes plus the size of that span.
+fn find_missing_seat_id(pass_ids: &[i32]) -> Result<i32> {
+    let min_id = *pass_ids
+        .iter()
+        .min()
+        .ok_or_else(|| anyhow!("No boarding passes to find a seat between"))?;
+    let max_id = *pass_ids.iter().max().unwrap();
+
+    let span = (max_id - min_id + 1) as usize;
+    let mut occupied = vec![0u64; span.div_ceil(64)];
+    for id in pass_ids {
+        let offset = (id - min_id) as usize;
+        let (word, bit) = (offset / 64, 1 << (offset % 64));
+        if occupied[word] & bit != 0 {
+            return Err(anyhow!(
+                "Seat {} appears on more than one boarding pass",
+                id
+            ));
+        }
+        occupied[word] |= bit;
+    }
+
+    let mut missing = (0..span)
+        .filter(|offset| occupied[offset / 64] & (1 << (offset % 64)) == 0)
+        .map(|offset| min_id + offset as i32);
+
+    match (missing.next(), missing.next()) {
+        (Some(id), None) => Ok(id),
+        (None, _) => Err(anyhow!(
+            "No free seat between seats {} and {}",
+            min_id,
+            max_id
+        )),
+        (Some(first), Some(second)) => Err(anyhow!(
+            "Multiple free seats between seats {} and {}: {}, {}{}",
+            min_id,
+            max_id,
+            first,
+            second,
+            if missing.next().is_some() {
+                ", ..."
+            } else {
+                ""
+            }
+        )),
+    }
+}
+
+fn day_5() -> Result<()> {
+    let input = std::fs::read_to_string("res/day_5_input")?;
 
-    let pass_ids = passes
+    let pass_ids = parse_boarding_passes(&input)?
         .iter()
         .map(|(row, column)| seat_id(*row, *column))
         .collect::<Vec<_>>();
@@ -381,55 +506,158 @@ fn day_5() -> Result<()> {
     // 913
     println!("Day 5, part 1: {}", max_id);
 
-    let all_seats = (0..2i32.pow(7))
-        .flat_map(move |row| (0..2i32.pow(3)).map(move |column| (row, column)))
-        .collect::<HashSet<(i32, i32)>>();
+    let my_seat_id = find_missing_seat_id(&pass_ids)?;
 
-    let mut empty_seats = all_seats;
-    for pass in passes {
-        empty_seats.remove(&pass);
-    }
+    // 717
+    println!("Day 5, part 2: {}", my_seat_id);
+
+    Ok(())
+}
+
+fn day_5_seat_map() -> Result<()> {
+    let input = std::fs::read_to_string("res/day_5_input")?;
 
-    let my_seat = empty_seats
+    let pass_ids = parse_boarding_passes(&input)?
         .iter()
-        .find(|(row, column)| {
-            let id = seat_id(*row, *column);
-            pass_ids.contains(&(id - 1)) && pass_ids.contains(&(id + 1))
-        })
-        .ok_or_else(|| anyhow!("Couldn't find my seat"))?;
-    let my_seat_id = seat_id(my_seat.0, my_seat.1);
+        .map(|(row, column)| seat_id(*row, *column))
+        .collect::<Vec<_>>();
+    let seats = cabin_seats(&pass_ids);
 
-    // 717
-    println!("Day 5, part 2: {}", my_seat_id);
+    println!("Day 5, seat map ('#' occupied, '.' free, ' ' missing):");
+    print!("{}", render_seat_map(&seats));
+
+    println!("Day 5, free seat runs:");
+    for (seat, ids) in free_seat_runs(&seats) {
+        let kind = match seat {
+            CabinSeat::Missing if *ids.start() == 0 => "missing front",
+            CabinSeat::Missing => "missing back",
+            _ => "gap",
+        };
+        println!(
+            "  {}..={} ({} seats, {})",
+            ids.start(),
+            ids.end(),
+            ids.end() - ids.start() + 1,
+            kind
+        );
+    }
 
     Ok(())
 }
 
+#[derive(Debug)]
+struct CustomsGroup {
+    // One bit per question for each person, with bit 0 for question 'a'
+    people: Vec<u32>,
+}
+
+const NUM_CUSTOMS_QUESTIONS: usize = 26;
+
+impl CustomsGroup {
+    fn question_counts(&self) -> [usize; NUM_CUSTOMS_QUESTIONS] {
+        let mut counts = [0; NUM_CUSTOMS_QUESTIONS];
+        for answers in &self.people {
+            for (question, count) in counts.iter_mut().enumerate() {
+                *count += (answers >> question & 1) as usize;
+            }
+        }
+        counts
+    }
+
+    // The questions which at least `quorum` people in the group answered yes to
+    fn answered_by_at_least(&self, quorum: usize) -> u32 {
+        self.question_counts()
+            .iter()
+            .enumerate()
+            .filter(|(_question, count)| **count > 0 && **count >= quorum)
+            .fold(0, |answers, (question, _count)| answers | 1 << question)
+    }
+
+    fn answered_by_anyone(&self) -> u32 {
+        self.people
+            .iter()
+            .fold(0, |answers, person| answers | person)
+    }
+
+    fn answered_by_everyone(&self) -> u32 {
+        self.people
+            .iter()
+            .fold(!0, |answers, person| answers & person)
+            & self.answered_by_anyone()
+    }
+
+    // The proportion of questions answered by anyone which were answered by everyone
+    fn agreement(&self) -> f64 {
+        match self.answered_by_anyone().count_ones() {
+            0 => 1.0,
+            any => self.answered_by_everyone().count_ones() as f64 / any as f64,
+        }
+    }
+}
+
+fn normalize_line_endings(input: &str) -> String {
+    input.replace("\r\n", "\n").replace('\r', "\n")
+}
+
+// Parses customs forms with one person per line, where any run of blank lines separates groups.
+// Every character which isn't an answer from 'a' to 'z' is reported along with its position.
+fn parse_customs_groups(input: &str) -> Result<Vec<CustomsGroup>> {
+    let mut groups = Vec::new();
+    let mut people = Vec::new();
+    let mut invalid = Vec::new();
+
+    for (line_index, line) in normalize_line_endings(input).lines().enumerate() {
+        if line.trim().is_empty() {
+            if !people.is_empty() {
+                groups.push(CustomsGroup {
+                    people: std::mem::take(&mut people),
+                });
+            }
+            continue;
+        }
+
+        let mut answers = 0u32;
+        for (column_index, answer) in line.chars().enumerate() {
+            match answer {
+                'a'..='z' => answers |= 1 << (answer as u32 - 'a' as u32),
+                _ => invalid.push(format!(
+                    "{:?} at line {}, column {}",
+                    answer,
+                    line_index + 1,
+                    column_index + 1
+                )),
+            }
+        }
+        people.push(answers);
+    }
+
+    if !people.is_empty() {
+        groups.push(CustomsGroup { people });
+    }
+
+    if !invalid.is_empty() {
+        return Err(anyhow!(
+            "Unexpected answers in customs forms: {}",
+            invalid.join(", ")
+        ));
+    }
+
+    Ok(groups)
+}
+
 fn day_6() -> Result<()> {
     let input = std::fs::read_to_string("res/day_6_input")?;
+    let groups = parse_customs_groups(&input)?;
 
-    let any_yes = input
-        .split("\n\n")
-        .map(|group| {
-            group
-                .lines()
-                .flat_map(|person| person.chars())
-                .unique()
-                .count()
-        })
-        .sum::<usize>();
+    let any_yes = groups
+        .iter()
+        .map(|group| group.answered_by_anyone().count_ones())
+        .sum::<u32>();
 
-    let all_yes = input
-        .split("\n\n")
-        .map(|group| {
-            group
-                .lines()
-                .flat_map(|person| person.chars())
-                .unique()
-                .filter(|answer| group.lines().all(|line| line.contains(*answer)))
-                .count()
-        })
-        .sum::<usize>();
+    let all_yes = groups
+        .iter()
+        .map(|group| group.answered_by_everyone().count_ones())
+        .sum::<u32>();
     // 6335
     println!("Day 6, part 1: {}", any_yes);
     // 3392
@@ -438,6 +666,64 @@ fn day_6() -> Result<()> {
     Ok(())
 }
 
+fn day_6_quorum(quorum: usize) -> Result<()> {
+    let input = std::fs::read_to_string("res/day_6_input")?;
+    let groups = parse_customs_groups(&input)?;
+
+    let quorum_yes = groups
+        .iter()
+        .map(|group| group.answered_by_at_least(quorum).count_ones())
+        .sum::<u32>();
+    println!(
+        "Day 6, questions answered by at least {} people: {}",
+        quorum, quorum_yes
+    );
+
+    let mut histogram = [(0, 0); NUM_CUSTOMS_QUESTIONS];
+    for group in &groups {
+        let counts = group.question_counts();
+        let quorate = group.answered_by_at_least(quorum);
+        for (question, (people, quorate_groups)) in histogram.iter_mut().enumerate() {
+            *people += counts[question];
+            *quorate_groups += (quorate >> question & 1) as usize;
+        }
+    }
+    println!("Day 6, answers per question (people, groups reaching quorum):");
+    for (question, (people, quorate_groups)) in histogram.iter().enumerate() {
+        println!(
+            "  {}: {:5} {:5}",
+            (b'a' + question as u8) as char,
+            people,
+            quorate_groups
+        );
+    }
+
+    let by_agreement = groups
+        .iter()
+        .enumerate()
+        .map(|(i, group)| (i, group.agreement()))
+        .collect::<Vec<_>>();
+    let highest = by_agreement
+        .iter()
+        .max_by(|a, b| a.1.partial_cmp(&b.1).unwrap())
+        .ok_or_else(|| anyhow!("No customs groups"))?;
+    let lowest = by_agreement
+        .iter()
+        .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap())
+        .unwrap();
+    for (label, (i, agreement)) in &[("highest", highest), ("lowest", lowest)] {
+        println!(
+            "Day 6, {} agreement: group {} ({} people), {:.0}%",
+            label,
+            i,
+            groups[*i].people.len(),
+            agreement * 100.0
+        );
+    }
+
+    Ok(())
+}
+
 fn parse_bag(input: &str) -> IResult<&str, String> {
     map(
         terminated(take_until(" bag"), alt((tag(" bags"), tag(" bag")))),
@@ -461,276 +747,2863 @@ fn parse_bag_rule(input: &str) -> IResult<&str, (String, Vec<(usize, String)>)>
     Ok((input, (subject, can_contain)))
 }
 
-fn bag_can_contain(
-    bag: &str,
-    can_contain: &str,
-    rules: &HashMap<String, Vec<(usize, String)>>,
-) -> bool {
-    for (_, colour) in &rules[bag] {
-        if colour == can_contain || bag_can_contain(&colour, can_contain, rules) {
-            return true;
-        }
-    }
-
-    false
-}
-
-fn num_bags_contained(bag: &str, rules: &HashMap<String, Vec<(usize, String)>>) -> usize {
-    rules[bag]
-        .iter()
-        .map(|(count, bag)| count * (num_bags_contained(bag, rules) + 1))
-        .sum()
-}
-
-fn day_7() -> Result<()> {
-    let input = std::fs::read_to_string("res/day_7_input")?;
+type BagRules = HashMap<String, Vec<(usize, String)>>;
 
-    let rules = input
+fn parse_bag_rules(input: &str) -> Result<BagRules> {
+    input
         .lines()
         .map(|line| {
             Ok(parse_bag_rule(line)
                 .map_err(|err| anyhow!("Error parsing bag rule: {:?}", err))?
                 .1)
         })
-        .collect::<Result<HashMap<String, Vec<(usize, String)>>>>()?;
-
-    let can_contain_shiny_gold = rules
-        .keys()
-        .filter(|bag| bag_can_contain(bag, "shiny gold", &rules))
-        .count();
-    // 45
-    println!("Day 7, part 1: {}", can_contain_shiny_gold);
-
-    let shiny_gold_contains = num_bags_contained("shiny gold", &rules);
-    // 7867
-    println!("Day 7, part 2: {}", shiny_gold_contains);
-
-    Ok(())
+        .collect()
 }
 
-#[derive(Debug, Clone)]
-enum Instruction {
-    Nop(i32),
-    Acc(i32),
-    Jmp(i32),
+// The bag rules with each colour interned to an index in `colours`. Construction fails if the
+// rules are cyclic, so queries can safely recurse through the graph.
+struct BagGraph {
+    colours: Vec<String>,
+    ids: HashMap<String, usize>,
+    // Whether each bag has a rule of its own, rather than only being named inside other rules
+    has_rule: Vec<bool>,
+    // For each bag, the (count, bag) pairs it must directly contain
+    contents: Vec<Vec<(usize, usize)>>,
+    // For each bag, the (count, bag) pairs which must directly contain it
+    containers: Vec<Vec<(usize, usize)>>,
+    num_contained_memo: RefCell<Vec<Option<Result<usize, usize>>>>,
+    max_depth_memo: RefCell<Vec<Option<usize>>>,
 }
 
-fn parse_instruction(input: &str) -> IResult<&str, Instruction> {
-    let (input, op) = alt((tag("nop "), tag("acc "), tag("jmp ")))(input)?;
-
-    let (input, sign) = alt((map(tag("+"), |_| 1), map(tag("-"), |_| -1)))(input)?;
+impl BagGraph {
+    fn new(rules: &BagRules) -> Result<BagGraph> {
+        let graph = BagGraph::unchecked(rules);
 
-    let (input, val) = map_res(digit1, str::parse::<i32>)(input)?;
-
-    let instruction = match op {
-        "nop " => Instruction::Nop(sign * val),
-        "acc " => Instruction::Acc(sign * val),
-        "jmp " => Instruction::Jmp(sign * val),
-        _ => panic!("Unrecognized op"),
-    };
+        for (id, contents) in graph.contents.iter().enumerate() {
+            if let Some((_count, inner)) =
+                contents.iter().find(|(_, inner)| !graph.has_rule[*inner])
+            {
+                return Err(anyhow!(
+                    "Bag {:?} contains {:?}, which has no rule",
+                    graph.colours[id],
+                    graph.colours[*inner]
+                ));
+            }
+        }
 
-    Ok((input, instruction))
-}
+        if let Some(cycle) = graph.find_cycle() {
+            return Err(anyhow!(
+                "Bag rules contain a cycle: {}",
+                cycle.iter().map(|id| &graph.colours[*id]).join(" -> ")
+            ));
+        }
 
-enum ProgramResult {
-    Terminated(i32),
-    InfiniteLoop(i32),
-}
+        Ok(graph)
+    }
 
-fn run_program(instructions: &[Instruction]) -> ProgramResult {
-    let mut visited = vec![false; instructions.len()];
-    let mut pc = 0;
-    let mut acc = 0;
+    // Builds the graph without checking the rules are complete and acyclic, so that broken rules
+    // can still be inspected. Colours which are only named inside other rules are included.
+    fn unchecked(rules: &BagRules) -> BagGraph {
+        let colours = rules
+            .iter()
+            .flat_map(|(colour, contents)| {
+                std::iter::once(colour).chain(contents.iter().map(|(_count, inner)| inner))
+            })
+            .sorted()
+            .dedup()
+            .cloned()
+            .collect::<Vec<String>>();
+        let ids = colours
+            .iter()
+            .enumerate()
+            .map(|(id, colour)| (colour.clone(), id))
+            .collect::<HashMap<String, usize>>();
+        let has_rule = colours
+            .iter()
+            .map(|colour| rules.contains_key(colour))
+            .collect::<Vec<bool>>();
 
-    loop {
-        if pc >= instructions.len() {
-            return ProgramResult::Terminated(acc);
-        }
-        if visited[pc] {
-            return ProgramResult::InfiniteLoop(acc);
+        let mut contents = vec![Vec::new(); colours.len()];
+        let mut containers = vec![Vec::new(); colours.len()];
+        for (colour, rule) in rules.iter().sorted_by_key(|(colour, _rule)| *colour) {
+            let id = ids[colour];
+            for (count, inner) in rule {
+                contents[id].push((*count, ids[inner]));
+                containers[ids[inner]].push((*count, id));
+            }
         }
 
-        visited[pc] = true;
-        match instructions[pc] {
-            Instruction::Nop(_) => pc += 1,
-            Instruction::Acc(val) => {
-                pc += 1;
-                acc += val;
-            }
-            Instruction::Jmp(val) => {
-                pc = (pc as i32 + val) as usize;
-            }
+        let len = colours.len();
+        BagGraph {
+            colours,
+            ids,
+            has_rule,
+            contents,
+            containers,
+            num_contained_memo: RefCell::new(vec![None; len]),
+            max_depth_memo: RefCell::new(vec![None; len]),
         }
     }
-}
 
-fn day_8() -> Result<()> {
-    let input = std::fs::read_to_string("res/day_8_input")?;
-    let instructions = input
-        .lines()
-        .map(|line| {
-            Ok(parse_instruction(line)
-                .map_err(|err| anyhow!("Error parsing instruction: {:?}", err))?
-                .1)
-        })
-        .collect::<Result<Vec<Instruction>>>()?;
+    fn id(&self, colour: &str) -> Result<usize> {
+        self.ids
+            .get(colour)
+            .copied()
+            .ok_or_else(|| anyhow!("Unknown bag colour {:?}", colour))
+    }
 
-    let acc_at_repeat = match run_program(&instructions) {
-        ProgramResult::InfiniteLoop(acc) => Ok(acc),
-        _ => Err(anyhow!("Program not expected to terminate")),
-    }?;
-    // 1446
-    println!("Day 8, part 1: {}", acc_at_repeat);
+    // Returns the path of a cycle, starting and ending at the same bag, if there is one
+    fn find_cycle(&self) -> Option<Vec<usize>> {
+        #[derive(Copy, Clone, PartialEq)]
+        enum State {
+            Unvisited,
+            OnPath,
+            Done,
+        }
 
-    let mut acc_at_term = Err(anyhow!("No modified program terminated"));
-    for i in 0..instructions.len() {
-        let modified_instructions = match instructions[i] {
-            Instruction::Nop(val) => {
-                let mut modified_instructions = instructions.clone();
-                modified_instructions[i] = Instruction::Jmp(val);
-                modified_instructions
-            }
-            Instruction::Jmp(val) => {
-                let mut modified_instructions = instructions.clone();
-                modified_instructions[i] = Instruction::Nop(val);
-                modified_instructions
+        fn visit(
+            graph: &BagGraph,
+            id: usize,
+            states: &mut [State],
+            path: &mut Vec<usize>,
+        ) -> Option<Vec<usize>> {
+            states[id] = State::OnPath;
+            path.push(id);
+
+            for (_count, inner) in &graph.contents[id] {
+                match states[*inner] {
+                    State::OnPath => {
+                        let start = path.iter().position(|id| id == inner).unwrap();
+                        let mut cycle = path[start..].to_vec();
+                        cycle.push(*inner);
+                        return Some(cycle);
+                    }
+                    State::Unvisited => {
+                        if let Some(cycle) = visit(graph, *inner, states, path) {
+                            return Some(cycle);
+                        }
+                    }
+                    State::Done => {}
+                }
             }
-            Instruction::Acc(_) => continue,
-        };
 
-        if let ProgramResult::Terminated(val) = run_program(&modified_instructions) {
-            acc_at_term = Ok(val);
-            break;
+            path.pop();
+            states[id] = State::Done;
+            None
         }
-    }
-    let acc_at_term = acc_at_term?;
-    // 1403
-    println!("Day 8, part 2: {}", acc_at_term);
-
-    Ok(())
-}
 
-fn day_9() -> Result<()> {
-    let input = std::fs::read_to_string("res/day_9_input")?;
-    let numbers = input
-        .lines()
-        .map(|line| {
-            line.parse::<u64>()
-                .with_context(|| format!("Error parsing line {:?}", line))
+        let mut states = vec![State::Unvisited; self.colours.len()];
+        (0..self.colours.len()).find_map(|id| match states[id] {
+            State::Unvisited => visit(self, id, &mut states, &mut Vec::new()),
+            _ => None,
         })
-        .collect::<Result<Vec<u64>>>()?;
+    }
 
-    let res = (25..numbers.len())
-        .find(|i| {
-            numbers[i - 25..*i]
-                .iter()
-                .combinations(2)
-                .map(|pair| pair.into_iter().sum::<u64>())
-                .find(|sum| *sum == numbers[*i])
-                .is_none()
-        })
-        .ok_or_else(|| {
-            anyhow!("No number found which doesn't equal sum of two of the previous 25 numbers")
-        })?;
+    // Breadth first search from `bag` along `edges`, returning every other bag reached along
+    // with the minimum depth at which it was found
+    fn reachable(&self, bag: &str, edges: &[Vec<(usize, usize)>]) -> Result<Vec<(&str, usize)>> {
+        Ok(self
+            .reachable_ids(self.id(bag)?, edges)
+            .into_iter()
+            .map(|(id, depth)| (self.colours[id].as_str(), depth))
+            .collect())
+    }
 
-    // 167829540
-    println!("Day 9, part 1: {}", numbers[res]);
-
-    fn find_contiguous_sum(numbers: &[u64], target_sum: u64) -> Result<&[u64]> {
-        for i in 0..numbers.len() - 1 {
-            for j in i + 2..numbers.len() + 1 {
-                match numbers[i..j].iter().sum::<u64>() {
-                    current_sum if current_sum > target_sum => break,
-                    current_sum if current_sum == target_sum => {
-                        return Ok(&numbers[i..j]);
-                    }
-                    _ => continue,
+    // The ids reachable from `start` along `edges`, with their depths, in breadth first order
+    fn reachable_ids(&self, start: usize, edges: &[Vec<(usize, usize)>]) -> Vec<(usize, usize)> {
+        let mut depths = vec![None; self.colours.len()];
+        depths[start] = Some(0);
+
+        let mut queue = VecDeque::new();
+        queue.push_back(start);
+        let mut reached = Vec::new();
+        while let Some(id) = queue.pop_front() {
+            let depth = depths[id].unwrap() + 1;
+            for (_count, next) in &edges[id] {
+                if depths[*next].is_none() {
+                    depths[*next] = Some(depth);
+                    reached.push((*next, depth));
+                    queue.push_back(*next);
                 }
             }
         }
 
-        Err(anyhow!(
-            "Failed to find contiguous range that sums to {}",
-            target_sum
-        ))
+        reached
     }
 
-    let range = find_contiguous_sum(&numbers, numbers[res])?;
-    let res2 = range.iter().min().unwrap() + range.iter().max().unwrap();
-    // 28045630
-    println!("Day 9, part 2: {}", res2);
+    // The bags which can eventually contain `bag`
+    fn containers_of(&self, bag: &str) -> Result<Vec<(&str, usize)>> {
+        self.reachable(bag, &self.containers)
+    }
 
-    Ok(())
-}
+    // The bags which `bag` must eventually contain
+    fn contents_of(&self, bag: &str) -> Result<Vec<(&str, usize)>> {
+        self.reachable(bag, &self.contents)
+    }
 
-fn day_10() -> Result<()> {
-    let input = std::fs::read_to_string("res/day_10_input")?;
-    let mut adaptors = input
-        .lines()
-        .map(|line| {
-            line.parse::<u64>()
-                .with_context(|| format!("Error parsing line {:?}", line))
+    // The total number of bags inside `bag`. This is computed natively where possible, but falls
+    // back to arbitrary precision if any subtree's count overflows a usize.
+    fn num_bags_contained(&self, bag: &str) -> Result<BagCount> {
+        let id = self.id(bag)?;
+        Ok(match self.num_bags_contained_id(id) {
+            Ok(count) => BagCount::Native(count),
+            Err(overflowed) => BagCount::Big {
+                count: self.num_bags_contained_big(id, &mut HashMap::new()),
+                overflowed: self.colours[overflowed].clone(),
+            },
         })
-        .collect::<Result<Vec<u64>>>()?;
+    }
 
-    // Add 0 jolt for the plug
-    adaptors.push(0);
+    // Fails with the bag whose subtree overflowed first
+    fn num_bags_contained_id(&self, bag: usize) -> Result<usize, usize> {
+        if let Some(res) = self.num_contained_memo.borrow()[bag] {
+            return res;
+        }
 
-    adaptors.sort_unstable();
+        let res = self.contents[bag]
+            .iter()
+            .try_fold(0usize, |total, (count, inner)| {
+                self.num_bags_contained_id(*inner)?
+                    .checked_add(1)
+                    .and_then(|inner_total| inner_total.checked_mul(*count))
+                    .and_then(|inner_total| inner_total.checked_add(total))
+                    .ok_or(bag)
+            });
+        self.num_contained_memo.borrow_mut()[bag] = Some(res);
+        res
+    }
 
-    // Add max + 3 jolt for my device
-    adaptors.push(adaptors.last().unwrap() + 3);
+    fn num_bags_contained_big(&self, bag: usize, memo: &mut HashMap<usize, BigUint>) -> BigUint {
+        if let Some(res) = memo.get(&bag) {
+            return res.clone();
+        }
 
-    let (num_1_jolt_diffs, num_3_jolt_diffs) = adaptors.iter().tuple_windows().fold(
-        (0, 0),
-        |(mut num_1_jolt_diffs, mut num_3_jolt_diffs), (i, j)| {
-            if j - i == 1 {
-                num_1_jolt_diffs += 1;
-            } else if j - i == 3 {
-                num_3_jolt_diffs += 1;
-            }
+        let mut res = BigUint::default();
+        for (count, inner) in &self.contents[bag] {
+            res += (self.num_bags_contained_big(*inner, memo) + 1u32) * *count;
+        }
+        memo.insert(bag, res.clone());
+        res
+    }
+}
 
-            (num_1_jolt_diffs, num_3_jolt_diffs)
-        },
-    );
+#[derive(Debug)]
+enum BagCount {
+    Native(usize),
+    Big {
+        count: BigUint,
+        // The bag whose subtree first exceeded a usize
+        overflowed: String,
+    },
+}
 
-    let res1 = num_1_jolt_diffs * num_3_jolt_diffs;
-    // 1625
-    println!("Day 10, part 1: {}", res1);
+impl BagCount {
+    fn overflow_diagnostic(&self) -> Option<String> {
+        match self {
+            BagCount::Native(_) => None,
+            BagCount::Big { overflowed, .. } => Some(format!(
+                "Count of bags inside {:?} exceeds {} bits, so was computed with big integers",
+                overflowed,
+                usize::BITS
+            )),
+        }
+    }
+}
 
-    // The joltage between each adapter either increases by 1 or 3 jolts.
-    // If it increases by 3, there is only 1 adaptor that can be used between the two joltages.
-    // If the joltage increases by 1 for a consecutive series of adaptors, there are multiple
-    // "paths" from the start joltage to the end joltage.
-    // First, split the adaptors in to groups where the joltage consecutively increases by 1,
-    // and find the length of each of those groups.
-    let run_lengths = adaptors
-        .iter()
-        .tuple_windows()
-        .group_by(|(prev, current)| **current == **prev + 1)
-        .into_iter()
-        .filter_map(|(key, group)| match key {
-            true => Some(group.count() + 1),
-            false => None,
-        })
-        .collect::<Vec<usize>>();
+impl std::fmt::Display for BagCount {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            BagCount::Native(count) => write!(f, "{}", count),
+            BagCount::Big { count, .. } => write!(f, "{}", count),
+        }
+    }
+}
 
-    // Calculate the number of paths through each of those groups, then the total
-    // number of paths from the plug to device is the product of each of those.
-    let num_paths = run_lengths
-        .iter()
-        .map(|length| match length {
-            1 => 1,
-            2 => 1,
-            3 => 2,
-            4 => 4,
-            5 => 7,
-            _ => unreachable!(),
-        })
-        .product::<usize>();
+fn day_7_reachable(direction: &str, bag: &str) -> Result<()> {
+    let input = std::fs::read_to_string("res/day_7_input")?;
+    let graph = BagGraph::new(&parse_bag_rules(&input)?)?;
 
-    // 3100448333024
-    println!("Day 10, part 2: {}", num_paths);
+    let reached = match direction {
+        "containers" => graph.containers_of(bag)?,
+        "contents" => graph.contents_of(bag)?,
+        _ => return Err(anyhow!("Unknown direction {:?}", direction)),
+    };
+
+    println!("Day 7, {} of {}: {}", direction, bag, reached.len());
+    for (colour, depth) in reached {
+        println!("  {} (depth {})", colour, depth);
+    }
+
+    Ok(())
+}
+
+impl BagGraph {
+    // Renders every bag inside `bag` as an indented tree, where each line shows the total number
+    // of that bag at that position, as well as how many each parent holds
+    fn expansion_tree(&self, bag: &str) -> Result<String> {
+        fn expand(
+            graph: &BagGraph,
+            id: usize,
+            multiplier: &BigUint,
+            depth: usize,
+            out: &mut String,
+        ) {
+            for (count, inner) in &graph.contents[id] {
+                let total = multiplier * *count;
+                out.push_str(&format!(
+                    "{}{} {} ({} per {})\n",
+                    "  ".repeat(depth),
+                    total,
+                    graph.colours[*inner],
+                    count,
+                    graph.colours[id]
+                ));
+                expand(graph, *inner, &total, depth + 1, out);
+            }
+        }
+
+        let id = self.id(bag)?;
+        let mut out = format!("{}\n", bag);
+        expand(self, id, &BigUint::from(1u32), 1, &mut out);
+        Ok(out)
+    }
+
+    // Every path of containment from `from` down to `to`, including both ends. Only bags which can
+    // contain `to` are visited, so the time taken is proportional to the paths found.
+    fn paths(&self, from: &str, to: &str) -> Result<Vec<Vec<&str>>> {
+        fn visit<'a>(
+            graph: &'a BagGraph,
+            id: usize,
+            to: usize,
+            leads_to: &[bool],
+            path: &mut Vec<&'a str>,
+            paths: &mut Vec<Vec<&'a str>>,
+        ) {
+            path.push(&graph.colours[id]);
+            if id == to {
+                paths.push(path.clone());
+            } else {
+                for (_count, inner) in &graph.contents[id] {
+                    if leads_to[*inner] {
+                        visit(graph, *inner, to, leads_to, path, paths);
+                    }
+                }
+            }
+            path.pop();
+        }
+
+        let to_id = self.id(to)?;
+        let mut leads_to = vec![false; self.colours.len()];
+        leads_to[to_id] = true;
+        for (colour, _depth) in self.containers_of(to)? {
+            leads_to[self.ids[colour]] = true;
+        }
+
+        let mut paths = Vec::new();
+        let from_id = self.id(from)?;
+        if leads_to[from_id] {
+            visit(self, from_id, to_id, &leads_to, &mut Vec::new(), &mut paths);
+        }
+        Ok(paths)
+    }
+
+    // The number of levels of bags nested inside `bag`
+    fn max_depth(&self, bag: &str) -> Result<usize> {
+        Ok(self.max_depth_id(self.id(bag)?))
+    }
+
+    fn max_depth_id(&self, bag: usize) -> usize {
+        if let Some(res) = self.max_depth_memo.borrow()[bag] {
+            return res;
+        }
+
+        let res = self.contents[bag]
+            .iter()
+            .map(|(_count, inner)| self.max_depth_id(*inner) + 1)
+            .max()
+            .unwrap_or(0);
+        self.max_depth_memo.borrow_mut()[bag] = Some(res);
+        res
+    }
+}
+
+fn day_7_explain(bag: &str) -> Result<()> {
+    let input = std::fs::read_to_string("res/day_7_input")?;
+    let graph = BagGraph::new(&parse_bag_rules(&input)?)?;
+
+    print!("{}", graph.expansion_tree(bag)?);
+    let count = graph.num_bags_contained(bag)?;
+    println!("Day 7, bags inside {}: {}", bag, count);
+    if let Some(diagnostic) = count.overflow_diagnostic() {
+        println!("Day 7, {}", diagnostic);
+    }
+    println!("Day 7, nesting depth of {}: {}", bag, graph.max_depth(bag)?);
+
+    Ok(())
+}
+
+fn day_7_paths(from: &str, to: &str) -> Result<()> {
+    let input = std::fs::read_to_string("res/day_7_input")?;
+    let graph = BagGraph::new(&parse_bag_rules(&input)?)?;
+
+    let paths = graph.paths(from, to)?;
+    println!("Day 7, paths from {} to {}: {}", from, to, paths.len());
+    for path in paths {
+        println!("  {}", path.join(" -> "));
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, PartialEq)]
+enum BagQuery<'a> {
+    Containers { bag: &'a str, transitive: bool },
+    Contents { bag: &'a str, transitive: bool },
+    Count(&'a str),
+    Depth(&'a str),
+    Leaves,
+    Roots,
+    Path(&'a str, &'a str),
+}
+
+fn parse_quoted_bag(input: &str) -> IResult<&str, &str> {
+    delimited(char('\''), take_till(|c| c == '\''), char('\''))(input)
+}
+
+fn parse_bag_query(input: &str) -> IResult<&str, BagQuery<'_>> {
+    fn with_bag<'a>(keyword: &'static str) -> impl FnMut(&'a str) -> IResult<&'a str, &'a str> {
+        preceded(pair(tag(keyword), space1), parse_quoted_bag)
+    }
+
+    all_consuming(delimited(
+        space0,
+        alt((
+            map(with_bag("contains*"), |bag| BagQuery::Containers {
+                bag,
+                transitive: true,
+            }),
+            map(with_bag("contains"), |bag| BagQuery::Containers {
+                bag,
+                transitive: false,
+            }),
+            map(with_bag("contents*"), |bag| BagQuery::Contents {
+                bag,
+                transitive: true,
+            }),
+            map(with_bag("contents"), |bag| BagQuery::Contents {
+                bag,
+                transitive: false,
+            }),
+            map(with_bag("count"), BagQuery::Count),
+            map(with_bag("depth"), BagQuery::Depth),
+            map(tag("leaves"), |_| BagQuery::Leaves),
+            map(tag("roots"), |_| BagQuery::Roots),
+            map(
+                separated_pair(
+                    with_bag("path"),
+                    delimited(space0, tag("->"), space0),
+                    parse_quoted_bag,
+                ),
+                |(from, to)| BagQuery::Path(from, to),
+            ),
+        )),
+        space0,
+    ))(input)
+}
+
+impl BagGraph {
+    // Evaluates a query, returning the lines of its result
+    fn query(&self, query: &BagQuery) -> Result<Vec<String>> {
+        Ok(match *query {
+            BagQuery::Containers {
+                bag,
+                transitive: false,
+            } => self.containers[self.id(bag)?]
+                .iter()
+                .map(|(count, id)| format!("{} ({} per bag)", self.colours[*id], count))
+                .collect(),
+            BagQuery::Contents {
+                bag,
+                transitive: false,
+            } => self.contents[self.id(bag)?]
+                .iter()
+                .map(|(count, id)| format!("{} {}", count, self.colours[*id]))
+                .collect(),
+            BagQuery::Containers {
+                bag,
+                transitive: true,
+            } => self
+                .containers_of(bag)?
+                .into_iter()
+                .map(|(colour, depth)| format!("{} (depth {})", colour, depth))
+                .collect(),
+            BagQuery::Contents {
+                bag,
+                transitive: true,
+            } => self
+                .contents_of(bag)?
+                .into_iter()
+                .map(|(colour, depth)| format!("{} (depth {})", colour, depth))
+                .collect(),
+            BagQuery::Count(bag) => {
+                let count = self.num_bags_contained(bag)?;
+                std::iter::once(count.to_string())
+                    .chain(count.overflow_diagnostic())
+                    .collect()
+            }
+            BagQuery::Depth(bag) => vec![self.max_depth(bag)?.to_string()],
+            BagQuery::Leaves => (0..self.colours.len())
+                .filter(|id| self.contents[*id].is_empty())
+                .map(|id| self.colours[id].clone())
+                .collect(),
+            BagQuery::Roots => (0..self.colours.len())
+                .filter(|id| self.containers[*id].is_empty())
+                .map(|id| self.colours[id].clone())
+                .collect(),
+            BagQuery::Path(from, to) => self
+                .paths(from, to)?
+                .into_iter()
+                .map(|path| path.join(" -> "))
+                .collect(),
+        })
+    }
+
+    fn run_query(&self, input: &str) -> Result<Vec<String>> {
+        let (_, query) =
+            parse_bag_query(input).map_err(|err| anyhow!("Error parsing query: {:?}", err))?;
+        self.query(&query)
+    }
+}
+
+fn day_7_query(query: Option<&str>) -> Result<()> {
+    let input = std::fs::read_to_string("res/day_7_input")?;
+    let graph = BagGraph::new(&parse_bag_rules(&input)?)?;
+
+    if let Some(query) = query {
+        for line in graph.run_query(query)? {
+            println!("{}", line);
+        }
+        return Ok(());
+    }
+
+    // Without a query, read them interactively from stdin until EOF
+    let stdin = std::io::stdin();
+    loop {
+        print!("> ");
+        std::io::stdout().flush()?;
+
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line)? == 0 {
+            break;
+        }
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        match graph.run_query(line.trim_end()) {
+            Ok(lines) => lines.iter().for_each(|line| println!("{}", line)),
+            Err(err) => println!("{}", err),
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Copy, Clone)]
+enum GraphFormat {
+    Dot,
+    Mermaid,
+}
+
+impl GraphFormat {
+    fn parse(format: &str) -> Result<GraphFormat> {
+        match format {
+            "dot" => Ok(GraphFormat::Dot),
+            "mermaid" => Ok(GraphFormat::Mermaid),
+            _ => Err(anyhow!("Unknown graph format {:?}", format)),
+        }
+    }
+}
+
+impl BagGraph {
+    // Writes the rules as a graph with an edge from each bag to the bags it contains, labelled with
+    // the quantity. If `from` or `to` are given, only bags reachable from or able to reach those
+    // bags are included. Bags without a rule are dashed and edges which are part of a cycle are red,
+    // so that broken rules can be exported too.
+    fn export(&self, format: GraphFormat, from: Option<&str>, to: Option<&str>) -> Result<String> {
+        let mut included = vec![true; self.colours.len()];
+        let mut restrict = |bag: &str, edges: &[Vec<(usize, usize)>]| -> Result<()> {
+            let start = self.id(bag)?;
+            let mut reachable = vec![false; self.colours.len()];
+            reachable[start] = true;
+            for (id, _depth) in self.reachable_ids(start, edges) {
+                reachable[id] = true;
+            }
+            for (included, reachable) in included.iter_mut().zip(reachable) {
+                *included &= reachable;
+            }
+            Ok(())
+        };
+        if let Some(bag) = from {
+            restrict(bag, &self.contents)?;
+        }
+        if let Some(bag) = to {
+            restrict(bag, &self.containers)?;
+        }
+
+        // Colours are quoted in both formats, and Mermaid takes entity codes in place of quotes
+        let label = |id: usize| match format {
+            GraphFormat::Dot => self.colours[id].replace('\\', "\\\\").replace('"', "\\\""),
+            GraphFormat::Mermaid => self.colours[id].replace('#', "#35;").replace('"', "#quot;"),
+        };
+
+        let mut out = String::new();
+        match format {
+            GraphFormat::Dot => out.push_str("digraph bags {\n"),
+            GraphFormat::Mermaid => out.push_str("graph TD\n"),
+        }
+
+        for id in (0..self.colours.len()).filter(|id| included[*id]) {
+            let dangling = !self.has_rule[id];
+            match format {
+                GraphFormat::Dot => out.push_str(&format!(
+                    "    \"{}\"{};\n",
+                    label(id),
+                    if dangling { " [style=dashed]" } else { "" }
+                )),
+                GraphFormat::Mermaid => {
+                    out.push_str(&format!("    bag{}[\"{}\"]\n", id, label(id)));
+                    if dangling {
+                        out.push_str(&format!("    style bag{} stroke-dasharray: 5 5\n", id));
+                    }
+                }
+            }
+        }
+
+        // An edge is part of a cycle if the bag it leads to can contain the bag it comes from
+        let mut contained_by = vec![None; self.colours.len()];
+        let mut in_cycle = |id: usize, inner: usize| -> bool {
+            contained_by[inner].get_or_insert_with(|| {
+                let mut contained = vec![false; self.colours.len()];
+                for (id, _depth) in self.reachable_ids(inner, &self.contents) {
+                    contained[id] = true;
+                }
+                contained
+            })[id]
+        };
+
+        let mut num_edges = 0;
+        for (id, contents) in self.contents.iter().enumerate() {
+            for (count, inner) in contents {
+                if !included[id] || !included[*inner] {
+                    continue;
+                }
+                let cyclic = id == *inner || in_cycle(id, *inner);
+                match format {
+                    GraphFormat::Dot => out.push_str(&format!(
+                        "    \"{}\" -> \"{}\" [label=\"{}\"{}];\n",
+                        label(id),
+                        label(*inner),
+                        count,
+                        if cyclic { ", color=red" } else { "" }
+                    )),
+                    GraphFormat::Mermaid => {
+                        out.push_str(&format!("    bag{} -->|{}| bag{}\n", id, count, inner));
+                        if cyclic {
+                            out.push_str(&format!("    linkStyle {} stroke:red\n", num_edges));
+                        }
+                    }
+                }
+                num_edges += 1;
+            }
+        }
+
+        if let GraphFormat::Dot = format {
+            out.push_str("}\n");
+        }
+
+        Ok(out)
+    }
+}
+
+fn day_7_export(format: &str, options: &[&str]) -> Result<()> {
+    let format = GraphFormat::parse(format)?;
+    let mut from = None;
+    let mut to = None;
+    let mut path = "res/day_7_input";
+    for option in options.chunks(2) {
+        match option {
+            ["--from", bag] => from = Some(*bag),
+            ["--to", bag] => to = Some(*bag),
+            ["--input", file] => path = file,
+            _ => return Err(anyhow!("Unrecognized export option {:?}", option)),
+        }
+    }
+
+    let input =
+        std::fs::read_to_string(path).with_context(|| format!("Error reading {:?}", path))?;
+    let graph = BagGraph::unchecked(&parse_bag_rules(&input)?);
+
+    print!("{}", graph.export(format, from, to)?);
+
+    Ok(())
+}
+
+fn day_7() -> Result<()> {
+    let input = std::fs::read_to_string("res/day_7_input")?;
+    let graph = BagGraph::new(&parse_bag_rules(&input)?)?;
+
+    let can_contain_shiny_gold = graph.containers_of("shiny gold")?.len();
+    // 45
+    println!("Day 7, part 1: {}", can_contain_shiny_gold);
+
+    let shiny_gold_contains = graph.num_bags_contained("shiny gold")?;
+    // 7867
+    println!("Day 7, part 2: {}", shiny_gold_contains);
+    if let Some(diagnostic) = shiny_gold_contains.overflow_diagnostic() {
+        println!("Day 7, {}", diagnostic);
+    }
+
+    Ok(())
+}
+
+// An operation the handheld console can execute. `execute` applies the instruction's effects on the
+// machine's state, and `next_pc` gives the pc following it.
+struct Opcode {
+    mnemonic: &'static str,
+    // Identifies the opcode in bytecode, so must never change once assigned
+    id: u8,
+    execute: fn(&mut Machine, i32) -> Result<(), Fault>,
+    next_pc: NextPc,
+    // The opcode a corrupted instruction may have been swapped from
+    flipped: Option<&'static Opcode>,
+}
+
+#[derive(Copy, Clone)]
+enum NextPc {
+    // Depends only on the instruction's pc and argument, so is known without running the program
+    Static(fn(usize, i32) -> isize),
+    // Depends on the machine's state before the instruction executes, eg. for a conditional branch.
+    // None of the puzzle's opcodes need this, only those of other tables.
+    #[allow(dead_code)]
+    Dynamic(fn(&Machine, i32) -> isize),
+}
+
+impl Opcode {
+    // The pc following an instruction at `pc`, if it can be known without running the program
+    fn static_next_pc(&self, pc: usize, arg: i32) -> Option<isize> {
+        match self.next_pc {
+            NextPc::Static(next_pc) => Some(next_pc(pc, arg)),
+            NextPc::Dynamic(_) => None,
+        }
+    }
+}
+
+// An error which stops the handheld console part way through a program
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Fault {
+    JumpOutOfBounds { pc: usize, target: isize },
+    Overflow { pc: usize },
+}
+
+impl std::fmt::Display for Fault {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Fault::JumpOutOfBounds { pc, target } => {
+                write!(f, "Instruction {} jumped out of bounds to {}", pc, target)
+            }
+            Fault::Overflow { pc } => write!(f, "Instruction {} overflowed", pc),
+        }
+    }
+}
+
+// Opcodes are compared by identity, as tables other than `OPCODES` may reuse a mnemonic
+impl PartialEq for Opcode {
+    fn eq(&self, other: &Opcode) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl std::fmt::Debug for Opcode {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.mnemonic)
+    }
+}
+
+static NOP: Opcode = Opcode {
+    mnemonic: "nop",
+    id: 0,
+    execute: |_machine, _arg| Ok(()),
+    next_pc: NextPc::Static(|pc, _arg| pc as isize + 1),
+    flipped: Some(&JMP),
+};
+
+static ACC: Opcode = Opcode {
+    mnemonic: "acc",
+    id: 1,
+    execute: |machine, arg| {
+        machine.acc = machine
+            .acc
+            .checked_add(arg)
+            .ok_or(Fault::Overflow { pc: machine.pc })?;
+        Ok(())
+    },
+    next_pc: NextPc::Static(|pc, _arg| pc as isize + 1),
+    flipped: None,
+};
+
+static JMP: Opcode = Opcode {
+    mnemonic: "jmp",
+    id: 2,
+    execute: |_machine, _arg| Ok(()),
+    next_pc: NextPc::Static(|pc, arg| pc as isize + arg as isize),
+    flipped: Some(&NOP),
+};
+
+// The opcodes of the puzzle's handheld console. Assembling, bytecode and the debugger take an
+// opcode table, so other instructions only need an `Opcode` in a
+// table passed to them.
+static OPCODES: &[&Opcode] = &[&NOP, &ACC, &JMP];
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct Instruction {
+    opcode: &'static Opcode,
+    arg: i32,
+}
+
+impl std::fmt::Display for Instruction {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} {:+}", self.opcode.mnemonic, self.arg)
+    }
+}
+
+fn parse_instruction_with<'a>(
+    opcodes: &'a [&'static Opcode],
+) -> impl Fn(&str) -> IResult<&str, Instruction> + 'a {
+    move |input| {
+        let (input, opcode) = map_res(terminated(alpha1, space1), |mnemonic| {
+            opcodes
+                .iter()
+                .find(|opcode| opcode.mnemonic == mnemonic)
+                .copied()
+                .ok_or_else(|| anyhow!("Unrecognized op {:?}", mnemonic))
+        })(input)?;
+
+        // The sign is parsed along with the digits, so i32::MIN is in range
+        let (input, arg) = map_res(
+            recognize(pair(alt((char('+'), char('-'))), digit1)),
+            str::parse::<i32>,
+        )(input)?;
+
+        Ok((input, Instruction { opcode, arg }))
+    }
+}
+
+const NUM_REGISTERS: usize = 8;
+
+// The state of the handheld console running a program
+#[derive(Clone)]
+struct Machine<'a> {
+    program: &'a [Instruction],
+    pc: usize,
+    acc: i32,
+    steps: usize,
+    // General purpose registers for use by additional opcodes
+    registers: [i32; NUM_REGISTERS],
+}
+
+impl<'a> Machine<'a> {
+    fn new(program: &'a [Instruction]) -> Machine<'a> {
+        Machine {
+            program,
+            pc: 0,
+            acc: 0,
+            steps: 0,
+            registers: [0; NUM_REGISTERS],
+        }
+    }
+
+    fn terminated(&self) -> bool {
+        self.pc >= self.program.len()
+    }
+
+    fn current_instruction(&self) -> Option<Instruction> {
+        self.program.get(self.pc).copied()
+    }
+
+    // Everything which determines how the machine continues
+    fn state(&self) -> MachineState {
+        MachineState {
+            pc: self.pc,
+            acc: self.acc,
+            registers: self.registers,
+        }
+    }
+
+    // Executes the instruction at the pc, returning it, or None if the program has terminated.
+    // The machine is left unchanged if the instruction faults.
+    fn step(&mut self) -> Result<Option<Instruction>, Fault> {
+        let instruction = match self.current_instruction() {
+            Some(instruction) => instruction,
+            None => return Ok(None),
+        };
+
+        let next_pc = match instruction.opcode.next_pc {
+            NextPc::Static(next_pc) => next_pc(self.pc, instruction.arg),
+            NextPc::Dynamic(next_pc) => next_pc(self, instruction.arg),
+        };
+        if next_pc < 0 || next_pc > self.program.len() as isize {
+            return Err(Fault::JumpOutOfBounds {
+                pc: self.pc,
+                target: next_pc,
+            });
+        }
+
+        (instruction.opcode.execute)(self, instruction.arg)?;
+        self.pc = next_pc as usize;
+        self.steps += 1;
+        Ok(Some(instruction))
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+struct MachineState {
+    pc: usize,
+    acc: i32,
+    registers: [i32; NUM_REGISTERS],
+}
+
+// Detects when execution starts repeating itself. When every opcode's next pc is static, the path
+// from a pc is fixed, so reaching any pc a second time means the program loops. Otherwise a pc may
+// be reached again in a different state, so only a repeat of the whole machine state is a loop.
+// Every state has to be kept then, so runs are limited to `DYNAMIC_STEP_LIMIT` steps by default.
+struct LoopDetector {
+    // The pc of every instruction executed so far
+    history: Vec<usize>,
+    // The index in `history` at which each pc was first executed
+    first_executed: Vec<Option<usize>>,
+    // For programs with dynamic opcodes, the index in `history` at which each state was executed
+    states: Option<HashMap<MachineState, usize>>,
+}
+
+const DYNAMIC_STEP_LIMIT: usize = 100_000;
+
+impl LoopDetector {
+    fn new(program: &[Instruction]) -> LoopDetector {
+        let dynamic = program
+            .iter()
+            .any(|instruction| matches!(instruction.opcode.next_pc, NextPc::Dynamic(_)));
+        LoopDetector {
+            history: Vec::new(),
+            first_executed: vec![None; program.len()],
+            states: if dynamic { Some(HashMap::new()) } else { None },
+        }
+    }
+
+    // The number of steps after which a run should stop, as the states kept would grow without end
+    fn step_limit(&self) -> Option<usize> {
+        self.states.as_ref().map(|_| DYNAMIC_STEP_LIMIT)
+    }
+
+    // The cycle of pcs which will repeat if the machine continues, if any. This and `record` are
+    // called on every step, so are worth inlining into the interpreter loop.
+    #[inline]
+    fn detected_loop(&self, machine: &Machine) -> Option<Vec<usize>> {
+        // A state can only repeat once its pc has
+        let start = (*self.first_executed.get(machine.pc)?)?;
+        let start = match &self.states {
+            None => start,
+            Some(states) => *states.get(&machine.state())?,
+        };
+        Some(self.history[start..].to_vec())
+    }
+
+    // Records that the machine is about to execute its current instruction
+    #[inline]
+    fn record(&mut self, machine: &Machine) {
+        let index = self.history.len();
+        if let Some(states) = &mut self.states {
+            states.entry(machine.state()).or_insert(index);
+        }
+        self.first_executed[machine.pc].get_or_insert(index);
+        self.history.push(machine.pc);
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum ProgramResult {
+    // Execution reached exactly the end of the program
+    Terminated(i32),
+    // The accumulator before the loop repeats, along with the pcs making up the loop
+    InfiniteLoop(i32, Vec<usize>),
+    Fault(Fault),
+    StepLimit(i32),
+}
+
+fn run_program(instructions: &[Instruction]) -> ProgramResult {
+    run_program_with(instructions, RunOptions::default())
+}
+
+#[derive(Debug)]
+struct TraceEntry {
+    step: usize,
+    pc: usize,
+    instruction: Instruction,
+    acc_before: i32,
+    acc_after: i32,
+}
+
+#[derive(Default)]
+struct RunOptions<'a> {
+    step_limit: Option<usize>,
+    // Records every executed instruction, if given
+    trace: Option<&'a mut Vec<TraceEntry>>,
+}
+
+fn run_program_with(instructions: &[Instruction], mut options: RunOptions) -> ProgramResult {
+    let mut loops = LoopDetector::new(instructions);
+    let mut machine = Machine::new(instructions);
+    let step_limit = options.step_limit.or(loops.step_limit());
+
+    loop {
+        if machine.terminated() {
+            return ProgramResult::Terminated(machine.acc);
+        }
+        if let Some(cycle) = loops.detected_loop(&machine) {
+            return ProgramResult::InfiniteLoop(machine.acc, cycle);
+        }
+        if step_limit == Some(machine.steps) {
+            return ProgramResult::StepLimit(machine.acc);
+        }
+
+        loops.record(&machine);
+        let (step, pc, acc_before) = (machine.steps, machine.pc, machine.acc);
+        let instruction = match machine.step() {
+            Ok(instruction) => instruction.unwrap(),
+            Err(fault) => return ProgramResult::Fault(fault),
+        };
+        if let Some(trace) = options.trace.as_mut() {
+            trace.push(TraceEntry {
+                step,
+                pc,
+                instruction,
+                acc_before,
+                acc_after: machine.acc,
+            });
+        }
+    }
+}
+
+// An instruction in a compiled program, along with the pc it's followed by. Whether a jump is in
+// bounds is known when compiling, so only its fault needs to be kept.
+#[derive(Copy, Clone)]
+enum CompiledOp {
+    // Not yet executed, so not yet compiled
+    Pending,
+    // acc, which is common enough to be worth adding to the accumulator without a call
+    Acc { arg: i32, next: usize },
+    // Any other opcode, which executes the instruction's opcode
+    Execute(usize),
+    OutOfBounds(isize),
+    // An opcode whose next pc depends on the machine's state, which is left to the interpreter
+    Interpret,
+}
+
+// A program compiled to a table of ops, one per pc, which a run dispatches on with a `match` like
+// the interpreter, but without working out next pcs or checking bounds. Each instruction is compiled
+// on its own, the first time any run executes it. As a run executes each instruction at most once,
+// compiling up front would cost more than a single run saves, and compiling an op costs about as
+// much as executing it, so later runs gain little from reusing it. Loops, step limits and faults are
+// detected when it runs, giving the same results as `run_program_with`.
+struct CompiledProgram<'a> {
+    program: &'a [Instruction],
+    ops: Vec<Cell<CompiledOp>>,
+    // Set once a run reaches an op which must be interpreted, after which every run is interpreted
+    interpret: Cell<bool>,
+}
+
+fn compile_program(instructions: &[Instruction]) -> CompiledProgram<'_> {
+    CompiledProgram {
+        program: instructions,
+        ops: vec![Cell::new(CompiledOp::Pending); instructions.len()],
+        interpret: Cell::new(false),
+    }
+}
+
+impl<'a> CompiledProgram<'a> {
+    fn compile_op(&self, pc: usize) -> CompiledOp {
+        let Instruction { opcode, arg } = self.program[pc];
+        let next = match opcode.static_next_pc(pc, arg) {
+            Some(next) => next,
+            None => return CompiledOp::Interpret,
+        };
+        if next < 0 || next > self.program.len() as isize {
+            return CompiledOp::OutOfBounds(next);
+        }
+
+        let next = next as usize;
+        if *opcode == ACC {
+            CompiledOp::Acc { arg, next }
+        } else {
+            CompiledOp::Execute(next)
+        }
+    }
+
+    fn op(&self, pc: usize) -> CompiledOp {
+        match self.ops[pc].get() {
+            CompiledOp::Pending => {
+                let op = self.compile_op(pc);
+                self.ops[pc].set(op);
+                op
+            }
+            op => op,
+        }
+    }
+
+    fn run(&self, step_limit: Option<usize>) -> ProgramResult {
+        // Runs are deterministic, so an interpreted run from the start gives the same result
+        let interpret = || {
+            self.interpret.set(true);
+            run_program_with(
+                self.program,
+                RunOptions {
+                    step_limit,
+                    ..RunOptions::default()
+                },
+            )
+        };
+        if self.interpret.get() {
+            return interpret();
+        }
+
+        let len = self.ops.len();
+        let step_limit = step_limit.unwrap_or(usize::MAX);
+        // The pcs executed so far, and one more than the index in it at which each pc was
+        // executed, or 0 if it hasn't been. Each pc is executed at most once, so the history never
+        // needs to grow.
+        let mut history = Vec::with_capacity(len);
+        let mut executed_at = vec![0; len];
+        let mut machine = Machine::new(self.program);
+        let mut pc = 0;
+        let mut steps = 0;
+
+        loop {
+            if pc == len {
+                return ProgramResult::Terminated(machine.acc);
+            }
+            if executed_at[pc] != 0 {
+                let cycle = history[executed_at[pc] - 1..].to_vec();
+                return ProgramResult::InfiniteLoop(machine.acc, cycle);
+            }
+            if steps == step_limit {
+                return ProgramResult::StepLimit(machine.acc);
+            }
+
+            history.push(pc);
+            executed_at[pc] = history.len();
+            pc = match self.op(pc) {
+                CompiledOp::Acc { arg, next } => match machine.acc.checked_add(arg) {
+                    Some(acc) => {
+                        machine.acc = acc;
+                        next
+                    }
+                    None => return ProgramResult::Fault(Fault::Overflow { pc }),
+                },
+                CompiledOp::Execute(next) => {
+                    let instruction = self.program[pc];
+                    machine.pc = pc;
+                    machine.steps = steps;
+                    if let Err(fault) = (instruction.opcode.execute)(&mut machine, instruction.arg)
+                    {
+                        return ProgramResult::Fault(fault);
+                    }
+                    next
+                }
+                CompiledOp::OutOfBounds(target) => {
+                    return ProgramResult::Fault(Fault::JumpOutOfBounds { pc, target })
+                }
+                CompiledOp::Interpret => return interpret(),
+                CompiledOp::Pending => unreachable!(),
+            };
+            steps += 1;
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+enum TraceFormat {
+    Jsonl,
+    Csv,
+}
+
+fn format_trace(trace: &[TraceEntry], format: TraceFormat) -> String {
+    let mut out = String::new();
+    if let TraceFormat::Csv = format {
+        out.push_str("step,pc,instruction,acc_before,acc_after\n");
+    }
+
+    for entry in trace {
+        out.push_str(&match format {
+            TraceFormat::Jsonl => format!(
+                "{{\"step\":{},\"pc\":{},\"instruction\":\"{}\",\"acc_before\":{},\"acc_after\":{}}}\n",
+                entry.step, entry.pc, entry.instruction, entry.acc_before, entry.acc_after
+            ),
+            TraceFormat::Csv => format!(
+                "{},{},{},{},{}\n",
+                entry.step, entry.pc, entry.instruction, entry.acc_before, entry.acc_after
+            ),
+        });
+    }
+
+    out
+}
+
+#[derive(Debug)]
+struct Repair {
+    index: usize,
+    original: Instruction,
+    replacement: Instruction,
+}
+
+impl Repair {
+    fn apply(&self, instructions: &[Instruction]) -> Vec<Instruction> {
+        let mut repaired = instructions.to_vec();
+        repaired[self.index] = self.replacement;
+        repaired
+    }
+}
+
+// Finds the single instruction which, when swapped for its flipped opcode, makes the program
+// terminate.
+fn repair_program(instructions: &[Instruction]) -> Result<Repair> {
+    if let ProgramResult::Terminated(acc) = run_program(instructions) {
+        return Err(anyhow!("Program already terminates with acc {}", acc));
+    }
+
+    let mut candidates = repair_candidates(instructions);
+    match candidates.len() {
+        0 => Err(anyhow!(
+            "No single flipped instruction makes the program terminate"
+        )),
+        1 => Ok(candidates.remove(0)),
+        _ => Err(anyhow!(
+            "Multiple flipped instructions make the program terminate: {}",
+            candidates
+                .iter()
+                .map(|repair| format!(
+                    "{} ({} -> {})",
+                    repair.index, repair.original, repair.replacement
+                ))
+                .join(", ")
+        )),
+    }
+}
+
+// Finds every instruction executed by the program which would make it terminate if its opcode was
+// flipped, in the order they are executed. Every pc from which the unmodified program reaches the
+// end forms a tree rooted at the end, as each pc has a single successor. Walking it from the end
+// gives, for each pc, the range the accumulator moves through on the way, relative to its value
+// before the pc. A flip then terminates if its successor is in the tree but isn't below the flipped
+// instruction, which would send it round again, and the accumulator stays in range on the way. Only
+// acc is assumed to change the accumulator, and instructions with dynamic successors are never
+// assumed to lead to the end.
+fn repair_candidates(instructions: &[Instruction]) -> Vec<Repair> {
+    let len = instructions.len();
+    let acc_delta = |instruction: &Instruction| {
+        if *instruction.opcode == ACC {
+            i64::from(instruction.arg)
+        } else {
+            0
+        }
+    };
+
+    // Predecessors of each pc, with `len` standing for any pc past the end of the program
+    let mut predecessors = vec![Vec::new(); len + 1];
+    for (pc, instruction) in instructions.iter().enumerate() {
+        match instruction.opcode.static_next_pc(pc, instruction.arg) {
+            Some(next) if next >= 0 && next <= len as isize => predecessors[next as usize].push(pc),
+            _ => {}
+        }
+    }
+
+    // Each pc in the tree is numbered as the walk enters it, and `left` is the next number once its
+    // subtree is done, so the pcs below it are numbered in `entered[pc]..left[pc]`
+    let mut reaches_end = vec![false; len + 1];
+    let mut acc_range = vec![(0i64, 0i64); len + 1];
+    let mut entered = vec![0; len + 1];
+    let mut left = vec![0; len + 1];
+    let mut count = 0;
+    let mut stack = vec![(len, false)];
+    while let Some((pc, done)) = stack.pop() {
+        if done {
+            left[pc] = count;
+            continue;
+        }
+        reaches_end[pc] = true;
+        entered[pc] = count;
+        count += 1;
+        if pc < len {
+            let instruction = &instructions[pc];
+            let next = instruction
+                .opcode
+                .static_next_pc(pc, instruction.arg)
+                .unwrap() as usize;
+            let (low, high) = acc_range[next];
+            let delta = acc_delta(instruction);
+            acc_range[pc] = ((delta + low).min(0), (delta + high).max(0));
+        }
+        stack.push((pc, true));
+        stack.extend(predecessors[pc].iter().map(|pred| (*pred, false)));
+    }
+    let below = |pc: usize, other: usize| {
+        reaches_end[pc] && entered[pc] <= entered[other] && entered[other] < left[pc]
+    };
+
+    // Follow the program as it runs, as its path may depend on dynamic opcodes
+    let mut candidates = Vec::new();
+    let mut considered = vec![false; len];
+    let mut machine = Machine::new(instructions);
+    let mut loops = LoopDetector::new(instructions);
+    while !machine.terminated()
+        && loops.detected_loop(&machine).is_none()
+        && loops.step_limit() != Some(machine.steps)
+    {
+        let pc = machine.pc;
+        let instruction = instructions[pc];
+        if let Some(flipped) = instruction.opcode.flipped.filter(|_| !considered[pc]) {
+            considered[pc] = true;
+            let replacement = Instruction {
+                opcode: flipped,
+                arg: instruction.arg,
+            };
+            let terminates = match flipped.static_next_pc(pc, instruction.arg) {
+                Some(next) if next >= 0 && next <= len as isize => {
+                    let next = next as usize;
+                    let acc = i64::from(machine.acc) + acc_delta(&replacement);
+                    let (low, high) = acc_range[next];
+                    reaches_end[next]
+                        && !below(pc, next)
+                        && acc + low >= i64::from(i32::MIN)
+                        && acc + high <= i64::from(i32::MAX)
+                }
+                _ => false,
+            };
+            if terminates {
+                candidates.push(Repair {
+                    index: pc,
+                    original: instruction,
+                    replacement,
+                });
+            }
+        }
+
+        loops.record(&machine);
+        if machine.step().is_err() {
+            break;
+        }
+    }
+
+    candidates
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum BlockSuccessor {
+    Block(usize),
+    Exit,
+    OutOfBounds(isize),
+    // Decided by the machine's state when the block's last instruction runs
+    Dynamic,
+}
+
+#[derive(Debug)]
+struct BasicBlock {
+    // The range of pcs in the block
+    start: usize,
+    end: usize,
+    successor: BlockSuccessor,
+}
+
+// The basic blocks of a handheld program. As every instruction with a static next pc has a single
+// successor, each block has exactly one successor too, unless it ends with a dynamic next pc. Any
+// block could follow one of those, so all blocks after a reachable one are considered reachable.
+struct ControlFlowGraph {
+    blocks: Vec<BasicBlock>,
+    // The block containing each pc
+    block_of: Vec<usize>,
+    // Whether each block is reachable from the start of the program
+    reachable: Vec<bool>,
+}
+
+impl ControlFlowGraph {
+    fn new(instructions: &[Instruction]) -> ControlFlowGraph {
+        let len = instructions.len();
+        let next_pc = |pc: usize| {
+            instructions[pc]
+                .opcode
+                .static_next_pc(pc, instructions[pc].arg)
+        };
+
+        // Blocks start at the beginning of the program, at jump targets, and after jumps
+        let mut leaders = vec![false; len + 1];
+        leaders[0] = true;
+        for pc in 0..len {
+            let next = next_pc(pc);
+            if next != Some(pc as isize + 1) {
+                leaders[pc + 1] = true;
+                match next {
+                    Some(next) if next >= 0 && next <= len as isize => {
+                        leaders[next as usize] = true
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        let starts = (0..len).filter(|pc| leaders[*pc]).collect::<Vec<usize>>();
+        let mut block_of = vec![0; len];
+        for (block, (start, end)) in starts
+            .iter()
+            .copied()
+            .zip(starts.iter().skip(1).copied().chain(std::iter::once(len)))
+            .enumerate()
+        {
+            block_of[start..end].iter_mut().for_each(|b| *b = block);
+        }
+
+        let blocks = starts
+            .iter()
+            .enumerate()
+            .map(|(block, start)| {
+                let end = starts.get(block + 1).copied().unwrap_or(len);
+                let successor = match next_pc(end - 1) {
+                    None => BlockSuccessor::Dynamic,
+                    Some(next) if next == len as isize => BlockSuccessor::Exit,
+                    Some(next) if next < 0 || next > len as isize => {
+                        BlockSuccessor::OutOfBounds(next)
+                    }
+                    Some(next) => BlockSuccessor::Block(block_of[next as usize]),
+                };
+                BasicBlock {
+                    start: *start,
+                    end,
+                    successor,
+                }
+            })
+            .collect::<Vec<BasicBlock>>();
+
+        let mut reachable = vec![false; blocks.len()];
+        let mut block = if blocks.is_empty() { None } else { Some(0) };
+        while let Some(current) = block.filter(|current| !reachable[*current]) {
+            reachable[current] = true;
+            block = match blocks[current].successor {
+                BlockSuccessor::Block(next) => Some(next),
+                BlockSuccessor::Dynamic => {
+                    reachable.iter_mut().for_each(|reachable| *reachable = true);
+                    None
+                }
+                _ => None,
+            };
+        }
+
+        ControlFlowGraph {
+            blocks,
+            block_of,
+            reachable,
+        }
+    }
+
+    fn unreachable_instructions(&self) -> Vec<usize> {
+        self.blocks
+            .iter()
+            .zip(&self.reachable)
+            .filter(|(_block, reachable)| !**reachable)
+            .flat_map(|(block, _reachable)| block.start..block.end)
+            .collect()
+    }
+
+    // The blocks making up each cycle in the graph
+    fn loops(&self) -> Vec<Vec<usize>> {
+        #[derive(Copy, Clone, PartialEq)]
+        enum State {
+            Unvisited,
+            OnPath,
+            Done,
+        }
+
+        let mut states = vec![State::Unvisited; self.blocks.len()];
+        let mut loops = Vec::new();
+        for start in 0..self.blocks.len() {
+            let mut path = Vec::new();
+            let mut block = Some(start);
+            while let Some(current) = block {
+                match states[current] {
+                    State::Done => break,
+                    State::OnPath => {
+                        let start = path.iter().position(|b| *b == current).unwrap();
+                        loops.push(path[start..].to_vec());
+                        break;
+                    }
+                    State::Unvisited => {
+                        states[current] = State::OnPath;
+                        path.push(current);
+                        block = match self.blocks[current].successor {
+                            BlockSuccessor::Block(next) => Some(next),
+                            _ => None,
+                        };
+                    }
+                }
+            }
+            for block in path {
+                states[block] = State::Done;
+            }
+        }
+
+        loops
+    }
+
+    fn exits(&self) -> Vec<usize> {
+        (0..self.blocks.len())
+            .filter(|block| self.blocks[*block].successor == BlockSuccessor::Exit)
+            .collect()
+    }
+
+    // Whether each block leads to the exit, walking back from the exit blocks. Dynamic successors
+    // are never assumed to lead there.
+    fn reaches_exit(&self) -> Vec<bool> {
+        let mut predecessors = vec![Vec::new(); self.blocks.len()];
+        for (block, basic_block) in self.blocks.iter().enumerate() {
+            if let BlockSuccessor::Block(next) = basic_block.successor {
+                predecessors[next].push(block);
+            }
+        }
+
+        let mut reaches_exit = vec![false; self.blocks.len()];
+        let mut stack = self.exits();
+        while let Some(block) = stack.pop() {
+            if !reaches_exit[block] {
+                reaches_exit[block] = true;
+                stack.extend(&predecessors[block]);
+            }
+        }
+        reaches_exit
+    }
+
+    // The flipped instructions which would make the exit reachable, found from the graph alone.
+    // Only reachable blocks which don't already lead to the exit are considered, so the path from a
+    // flipped successor can't come back through the flipped instruction.
+    fn exit_flips(&self, instructions: &[Instruction]) -> Vec<Repair> {
+        let len = instructions.len();
+        let reaches_exit = self.reaches_exit();
+        (0..self.blocks.len())
+            .filter(|block| self.reachable[*block] && !reaches_exit[*block])
+            .flat_map(|block| self.blocks[block].start..self.blocks[block].end)
+            .filter_map(|pc| {
+                let original = instructions[pc];
+                let flipped = original.opcode.flipped?;
+                let next = flipped.static_next_pc(pc, original.arg)?;
+                let leads_to_exit = next == len as isize
+                    || (next >= 0
+                        && next < len as isize
+                        && reaches_exit[self.block_of[next as usize]]);
+                leads_to_exit.then_some(Repair {
+                    index: pc,
+                    original,
+                    replacement: Instruction {
+                        opcode: flipped,
+                        arg: original.arg,
+                    },
+                })
+            })
+            .collect()
+    }
+
+    fn to_dot(&self, instructions: &[Instruction]) -> String {
+        let mut out = String::from("digraph program {\n    node [shape=box];\n");
+        for (block, basic_block) in self.blocks.iter().enumerate() {
+            let label = (basic_block.start..basic_block.end)
+                .map(|pc| format!("{}: {}\\l", pc, instructions[pc]))
+                .collect::<String>();
+            let style = if self.reachable[block] {
+                ""
+            } else {
+                ", style=dashed"
+            };
+            out.push_str(&format!(
+                "    block{} [label=\"{}\"{}];\n",
+                block, label, style
+            ));
+            match basic_block.successor {
+                BlockSuccessor::Block(next) => {
+                    out.push_str(&format!("    block{} -> block{};\n", block, next))
+                }
+                BlockSuccessor::Exit => out.push_str(&format!("    block{} -> exit;\n", block)),
+                BlockSuccessor::OutOfBounds(target) => out.push_str(&format!(
+                    "    block{} -> out_of_bounds [label=\"{}\"];\n",
+                    block, target
+                )),
+                BlockSuccessor::Dynamic => {
+                    out.push_str(&format!("    block{} -> dynamic [style=dotted];\n", block))
+                }
+            }
+        }
+        out.push_str("    exit [shape=doublecircle];\n");
+        if self
+            .blocks
+            .iter()
+            .any(|block| matches!(block.successor, BlockSuccessor::OutOfBounds(_)))
+        {
+            out.push_str("    out_of_bounds [shape=octagon];\n");
+        }
+        if self
+            .blocks
+            .iter()
+            .any(|block| block.successor == BlockSuccessor::Dynamic)
+        {
+            out.push_str("    dynamic [shape=diamond];\n");
+        }
+        out.push_str("}\n");
+        out
+    }
+}
+
+// Prints the program with labels in place of relative offsets for jumps within the program
+fn disassemble(instructions: &[Instruction]) -> String {
+    let len = instructions.len();
+    let target = |pc: usize, instruction: &Instruction| match instruction
+        .opcode
+        .static_next_pc(pc, instruction.arg)
+    {
+        Some(next) if next != pc as isize + 1 && next >= 0 && next <= len as isize => {
+            Some(next as usize)
+        }
+        _ => None,
+    };
+    let label = |pc: usize| {
+        if pc == len {
+            "end".to_string()
+        } else {
+            format!("L{}", pc)
+        }
+    };
+
+    let mut targets = vec![false; len + 1];
+    for (pc, instruction) in instructions.iter().enumerate() {
+        if let Some(target) = target(pc, instruction) {
+            targets[target] = true;
+        }
+    }
+
+    let mut out = String::new();
+    for (pc, instruction) in instructions.iter().enumerate() {
+        if targets[pc] {
+            out.push_str(&format!("{}:\n", label(pc)));
+        }
+        match target(pc, instruction) {
+            Some(target) => out.push_str(&format!(
+                "    {} {}\n",
+                instruction.opcode.mnemonic,
+                label(target)
+            )),
+            None => out.push_str(&format!("    {}\n", instruction)),
+        }
+    }
+    if targets[len] {
+        out.push_str(&format!("{}:\n", label(len)));
+    }
+
+    out
+}
+
+// Assembles programs as printed by `disassemble`, using the mnemonics of `opcodes`. Arguments are
+// either signed offsets, as in the puzzle input, or labels, which are defined on their own line as
+// `name:`.
+fn assemble(input: &str, opcodes: &[&'static Opcode]) -> Result<Vec<Instruction>> {
+    let mut labels = HashMap::new();
+    let mut lines = Vec::new();
+    for (line_index, line) in input.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        if let Some(label) = line.strip_suffix(':') {
+            if labels.insert(label, lines.len()).is_some() {
+                return Err(anyhow!(
+                    "Label {:?} redefined on line {}",
+                    label,
+                    line_index + 1
+                ));
+            }
+        } else {
+            lines.push((line_index, line));
+        }
+    }
+
+    lines
+        .iter()
+        .enumerate()
+        .map(|(pc, &(line_index, line))| {
+            let error = || anyhow!("Invalid instruction {:?} on line {}", line, line_index + 1);
+            if let Ok((_, instruction)) = all_consuming(parse_instruction_with(opcodes))(line) {
+                return Ok(instruction);
+            }
+
+            // Otherwise the argument must be a label
+            let label = verify(take_till1(|c: char| c.is_whitespace()), |label: &str| {
+                !label.starts_with(['+', '-'])
+            });
+            let (_, (mnemonic, label)) = all_consuming(separated_pair(alpha1, space1, label))(line)
+                .map_err(|_: nom::Err<nom::error::Error<&str>>| error())?;
+            let opcode = opcodes
+                .iter()
+                .find(|opcode| opcode.mnemonic == mnemonic)
+                .copied()
+                .ok_or_else(error)?;
+            let target = labels
+                .get(label)
+                .ok_or_else(|| anyhow!("Undefined label {:?} on line {}", label, line_index + 1))?;
+            Ok(Instruction {
+                opcode,
+                arg: *target as i32 - pc as i32,
+            })
+        })
+        .collect()
+}
+
+const BYTECODE_MAGIC: &[u8] = b"HHBC";
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(bytes: &mut impl Iterator<Item = u8>) -> Result<u64> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let byte = bytes
+            .next()
+            .ok_or_else(|| anyhow!("Unexpected end of bytecode"))?;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(anyhow!("Varint too long in bytecode"))
+}
+
+// Encodes the program as the magic number and instruction count, followed by each instruction's
+// opcode id and its zigzag encoded argument. Integers are written as LEB128 varints.
+fn encode_program(instructions: &[Instruction], opcodes: &[&'static Opcode]) -> Result<Vec<u8>> {
+    let mut out = BYTECODE_MAGIC.to_vec();
+    write_varint(&mut out, instructions.len() as u64);
+    for instruction in instructions {
+        if !opcodes.contains(&instruction.opcode) {
+            return Err(anyhow!("Opcode {:?} has no bytecode", instruction.opcode));
+        }
+        out.push(instruction.opcode.id);
+        write_varint(
+            &mut out,
+            ((instruction.arg << 1) ^ (instruction.arg >> 31)) as u32 as u64,
+        );
+    }
+    Ok(out)
+}
+
+fn decode_program(bytecode: &[u8], opcodes: &[&'static Opcode]) -> Result<Vec<Instruction>> {
+    let mut bytes = bytecode
+        .strip_prefix(BYTECODE_MAGIC)
+        .ok_or_else(|| anyhow!("Missing bytecode magic number"))?
+        .iter()
+        .copied();
+
+    let len = read_varint(&mut bytes)?;
+    let instructions = (0..len)
+        .map(|_| {
+            let opcode = bytes
+                .next()
+                .ok_or_else(|| anyhow!("Unexpected end of bytecode"))?;
+            let opcode = *opcodes
+                .iter()
+                .find(|candidate| candidate.id == opcode)
+                .ok_or_else(|| anyhow!("Unknown opcode {} in bytecode", opcode))?;
+            let arg = u32::try_from(read_varint(&mut bytes)?)
+                .map_err(|_| anyhow!("Argument out of range in bytecode"))?;
+            let arg = (arg >> 1) as i32 ^ -((arg & 1) as i32);
+            Ok(Instruction { opcode, arg })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    if bytes.next().is_some() {
+        return Err(anyhow!("Trailing data after bytecode"));
+    }
+
+    Ok(instructions)
+}
+
+// Loads a program from either bytecode or assembly
+fn load_program(path: &str, opcodes: &[&'static Opcode]) -> Result<Vec<Instruction>> {
+    let bytes = std::fs::read(path).with_context(|| format!("Error reading {:?}", path))?;
+    if bytes.starts_with(BYTECODE_MAGIC) {
+        decode_program(&bytes, opcodes)
+    } else {
+        assemble(std::str::from_utf8(&bytes)?, opcodes)
+    }
+}
+
+#[derive(Debug)]
+enum StopReason {
+    Breakpoint(usize),
+    AccChanged(i32, i32),
+    Terminated,
+    Fault(Fault),
+    // The pcs of the cycle which the program is about to repeat
+    InfiniteLoop(Vec<usize>),
+    StepLimit(usize),
+}
+
+// Where a breakpoint stops, either a pc or every instruction with an opcode
+enum BreakpointTarget {
+    Pc(usize),
+    Opcode(&'static str),
+}
+
+struct Debugger<'a> {
+    machine: Machine<'a>,
+    // The opcodes which breakpoints may name
+    opcodes: &'a [&'static Opcode],
+    pc_breakpoints: HashSet<usize>,
+    opcode_breakpoints: HashSet<&'static str>,
+    watch_acc: bool,
+    // Records every instruction executed so far
+    loops: LoopDetector,
+    // Whether execution has stopped before the current instruction, rather than not yet started
+    stopped: bool,
+}
+
+impl<'a> Debugger<'a> {
+    fn new(program: &'a [Instruction], opcodes: &'a [&'static Opcode]) -> Debugger<'a> {
+        Debugger {
+            machine: Machine::new(program),
+            opcodes,
+            pc_breakpoints: HashSet::new(),
+            opcode_breakpoints: HashSet::new(),
+            watch_acc: false,
+            loops: LoopDetector::new(program),
+            stopped: false,
+        }
+    }
+
+    // The cycle of pcs which will repeat if execution continues, if there is one
+    fn detected_loop(&self) -> Option<Vec<usize>> {
+        self.loops.detected_loop(&self.machine)
+    }
+
+    fn step(&mut self) -> Option<StopReason> {
+        self.stopped = true;
+        if let Some(limit) = self
+            .loops
+            .step_limit()
+            .filter(|limit| *limit == self.machine.steps)
+        {
+            return Some(StopReason::StepLimit(limit));
+        }
+        let before = self.machine.clone();
+        if let Err(fault) = self.machine.step().transpose()? {
+            return Some(StopReason::Fault(fault));
+        }
+
+        self.loops.record(&before);
+        let acc = before.acc;
+
+        if self.watch_acc && self.machine.acc != acc {
+            return Some(StopReason::AccChanged(acc, self.machine.acc));
+        }
+        if self.machine.terminated() {
+            return Some(StopReason::Terminated);
+        }
+        None
+    }
+
+    // Whether the instruction about to be executed has a breakpoint on it
+    fn breakpoint(&self) -> Option<StopReason> {
+        let instruction = self.machine.current_instruction()?;
+        if self.pc_breakpoints.contains(&self.machine.pc)
+            || self
+                .opcode_breakpoints
+                .contains(instruction.opcode.mnemonic)
+        {
+            Some(StopReason::Breakpoint(self.machine.pc))
+        } else {
+            None
+        }
+    }
+
+    fn resume(&mut self) -> StopReason {
+        // The instruction execution stopped before may be on the breakpoint it stopped at, so it's
+        // executed without checking
+        let mut resuming = self.stopped;
+        loop {
+            if self.machine.terminated() {
+                return StopReason::Terminated;
+            }
+            if let Some(cycle) = self.detected_loop() {
+                return StopReason::InfiniteLoop(cycle);
+            }
+            if !resuming {
+                if let Some(reason) = self.breakpoint() {
+                    self.stopped = true;
+                    return reason;
+                }
+            }
+            resuming = false;
+            if let Some(reason) = self.step() {
+                return reason;
+            }
+        }
+    }
+
+    fn describe(&self, pc: usize) -> String {
+        match self.machine.program.get(pc) {
+            Some(instruction) => format!("{:4}: {}", pc, instruction),
+            None => format!("{:4}: <end of program>", pc),
+        }
+    }
+
+    fn describe_stop(&self, reason: &StopReason) -> Vec<String> {
+        let mut lines = match reason {
+            StopReason::Breakpoint(pc) => vec![format!("Breakpoint at {}", pc)],
+            StopReason::AccChanged(old, new) => {
+                vec![format!("acc changed from {} to {}", old, new)]
+            }
+            StopReason::Terminated => {
+                vec![format!("Program terminated with acc {}", self.machine.acc)]
+            }
+            StopReason::Fault(fault) => vec![fault.to_string()],
+            StopReason::InfiniteLoop(cycle) => {
+                let mut lines = vec![format!(
+                    "Infinite loop of {} instructions detected with acc {}:",
+                    cycle.len(),
+                    self.machine.acc
+                )];
+                lines.extend(cycle.iter().map(|pc| self.describe(*pc)));
+                lines
+            }
+            StopReason::StepLimit(limit) => vec![format!(
+                "Step limit of {} reached with acc {}",
+                limit, self.machine.acc
+            )],
+        };
+        if !self.machine.terminated() {
+            lines.push(self.describe(self.machine.pc));
+        }
+        lines
+    }
+
+    fn command(&mut self, command: &str) -> Result<Vec<String>> {
+        let words = command.split_whitespace().collect::<Vec<&str>>();
+        let parse_target = |target: &str| match target.parse::<usize>() {
+            Ok(pc) => Ok(BreakpointTarget::Pc(pc)),
+            Err(_) => self
+                .opcodes
+                .iter()
+                .find(|opcode| opcode.mnemonic == target)
+                .map(|opcode| BreakpointTarget::Opcode(opcode.mnemonic))
+                .ok_or_else(|| anyhow!("Unknown breakpoint {:?}", target)),
+        };
+
+        Ok(match words.as_slice() {
+            ["step"] | ["s"] => match self.step().or_else(|| self.breakpoint()) {
+                Some(reason) => self.describe_stop(&reason),
+                None => vec![self.describe(self.machine.pc)],
+            },
+            ["step", count] | ["s", count] => {
+                let count = count
+                    .parse::<usize>()
+                    .with_context(|| format!("Invalid step count {:?}", count))?;
+                match (0..count).find_map(|_| self.step().or_else(|| self.breakpoint())) {
+                    Some(reason) => self.describe_stop(&reason),
+                    None => vec![self.describe(self.machine.pc)],
+                }
+            }
+            ["continue"] | ["c"] => {
+                let reason = self.resume();
+                self.describe_stop(&reason)
+            }
+            ["break", target] | ["b", target] => {
+                match parse_target(target)? {
+                    BreakpointTarget::Pc(pc) => self.pc_breakpoints.insert(pc),
+                    BreakpointTarget::Opcode(mnemonic) => self.opcode_breakpoints.insert(mnemonic),
+                };
+                vec![format!("Breakpoint set on {}", target)]
+            }
+            ["delete", target] | ["d", target] => {
+                match parse_target(target)? {
+                    BreakpointTarget::Pc(pc) => self.pc_breakpoints.remove(&pc),
+                    BreakpointTarget::Opcode(mnemonic) => self.opcode_breakpoints.remove(mnemonic),
+                };
+                vec![format!("Breakpoint removed from {}", target)]
+            }
+            ["watch", "acc"] => {
+                self.watch_acc = !self.watch_acc;
+                vec![format!(
+                    "Watch on acc {}",
+                    if self.watch_acc {
+                        "enabled"
+                    } else {
+                        "disabled"
+                    }
+                )]
+            }
+            ["history"] | ["history", _] => {
+                let count = match words.get(1) {
+                    Some(count) => count
+                        .parse::<usize>()
+                        .with_context(|| format!("Invalid history length {:?}", count))?,
+                    None => 10,
+                };
+                let history = &self.loops.history;
+                history[history.len().saturating_sub(count)..]
+                    .iter()
+                    .map(|pc| self.describe(*pc))
+                    .collect()
+            }
+            ["loop"] => match self.detected_loop() {
+                Some(cycle) => cycle.iter().map(|pc| self.describe(*pc)).collect(),
+                None => vec!["No loop detected yet".to_string()],
+            },
+            ["state"] | ["p"] => vec![
+                format!(
+                    "pc {}, acc {}, steps {}",
+                    self.machine.pc, self.machine.acc, self.machine.steps
+                ),
+                format!("registers {:?}", self.machine.registers),
+                self.describe(self.machine.pc),
+            ],
+            _ => return Err(anyhow!("Unrecognized debugger command {:?}", command)),
+        })
+    }
+}
+
+fn day_8_debug(path: Option<&str>) -> Result<()> {
+    let path = path.unwrap_or("res/day_8_input");
+    let instructions = load_program(path, OPCODES)?;
+    let mut debugger = Debugger::new(&instructions, OPCODES);
+
+    println!("{}", debugger.describe(0));
+    let stdin = std::io::stdin();
+    loop {
+        print!("(debug) ");
+        std::io::stdout().flush()?;
+
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line)? == 0 {
+            break;
+        }
+        match line.trim() {
+            "" => continue,
+            "quit" | "q" => break,
+            command => match debugger.command(command) {
+                Ok(lines) => lines.iter().for_each(|line| println!("{}", line)),
+                Err(err) => println!("{}", err),
+            },
+        }
+    }
+
+    Ok(())
+}
+
+fn day_8() -> Result<()> {
+    let instructions = load_program("res/day_8_input", OPCODES)?;
+
+    let acc_at_repeat = match run_program(&instructions) {
+        ProgramResult::InfiniteLoop(acc, _) => Ok(acc),
+        _ => Err(anyhow!("Program not expected to terminate")),
+    }?;
+    // 1446
+    println!("Day 8, part 1: {}", acc_at_repeat);
+
+    let repair = repair_program(&instructions)?;
+    let acc_at_term = match run_program(&repair.apply(&instructions)) {
+        ProgramResult::Terminated(acc) => Ok(acc),
+        _ => Err(anyhow!("Repaired program not expected to loop")),
+    }?;
+    // 1403
+    println!("Day 8, part 2: {}", acc_at_term);
+    println!(
+        "Day 8, repaired instruction {}: {} -> {}",
+        repair.index, repair.original, repair.replacement
+    );
+
+    Ok(())
+}
+
+fn day_8_trace(format: &str, program: &str) -> Result<()> {
+    let format = match format {
+        "jsonl" => TraceFormat::Jsonl,
+        "csv" => TraceFormat::Csv,
+        _ => return Err(anyhow!("Unknown trace format {:?}", format)),
+    };
+
+    let mut instructions = load_program("res/day_8_input", OPCODES)?;
+    match program {
+        "original" => {}
+        "patched" => instructions = repair_program(&instructions)?.apply(&instructions),
+        _ => return Err(anyhow!("Unknown program {:?}", program)),
+    }
+
+    let mut trace = Vec::new();
+    run_program_with(
+        &instructions,
+        RunOptions {
+            trace: Some(&mut trace),
+            ..RunOptions::default()
+        },
+    );
+    print!("{}", format_trace(&trace, format));
+
+    Ok(())
+}
+
+fn day_8_run(path: &str, options: &[&str]) -> Result<()> {
+    let step_limit = match options {
+        [] => None,
+        ["--step-limit", limit] => Some(
+            limit
+                .parse::<usize>()
+                .with_context(|| format!("Invalid step limit {:?}", limit))?,
+        ),
+        _ => return Err(anyhow!("Unrecognized run options {:?}", options)),
+    };
+
+    let instructions = load_program(path, OPCODES)?;
+
+    match run_program_with(
+        &instructions,
+        RunOptions {
+            step_limit,
+            ..RunOptions::default()
+        },
+    ) {
+        ProgramResult::Terminated(acc) => println!("Terminated with acc {}", acc),
+        ProgramResult::InfiniteLoop(acc, cycle) => println!(
+            "Infinite loop with acc {}: {}",
+            acc,
+            cycle.iter().join(" -> ")
+        ),
+        ProgramResult::Fault(fault) => println!("{}", fault),
+        ProgramResult::StepLimit(acc) => println!("Step limit reached with acc {}", acc),
+    }
+
+    Ok(())
+}
+
+fn day_8_disassemble(path: &str) -> Result<()> {
+    print!("{}", disassemble(&load_program(path, OPCODES)?));
+
+    Ok(())
+}
+
+fn day_8_assemble(path: &str, output: &str) -> Result<()> {
+    let instructions = load_program(path, OPCODES)?;
+    std::fs::write(output, encode_program(&instructions, OPCODES)?)
+        .with_context(|| format!("Error writing {:?}", output))?;
+
+    Ok(())
+}
+
+fn day_8_cfg(path: &str, options: &[&str]) -> Result<()> {
+    let instructions = load_program(path, OPCODES)?;
+    let cfg = ControlFlowGraph::new(&instructions);
+
+    match options {
+        [] => {}
+        ["--dot"] => {
+            print!("{}", cfg.to_dot(&instructions));
+            return Ok(());
+        }
+        _ => return Err(anyhow!("Unrecognized cfg options {:?}", options)),
+    }
+
+    let describe_block = |block: usize| {
+        let block = &cfg.blocks[block];
+        format!("{}..{}", block.start, block.end)
+    };
+
+    println!(
+        "Basic blocks: {} ({} reachable)",
+        cfg.blocks.len(),
+        cfg.reachable.iter().filter(|reachable| **reachable).count()
+    );
+    let unreachable = cfg.unreachable_instructions();
+    if unreachable.is_empty() {
+        println!("Unreachable instructions: none");
+    } else {
+        println!(
+            "Unreachable instructions: {}",
+            unreachable.iter().join(", ")
+        );
+    }
+    for cycle in cfg.loops() {
+        let reachable = if cfg.reachable[cycle[0]] {
+            "reachable"
+        } else {
+            "unreachable"
+        };
+        println!(
+            "Loop ({}): {}",
+            reachable,
+            cycle
+                .iter()
+                .map(|block| describe_block(*block))
+                .join(" -> ")
+        );
+    }
+    for block in cfg.exits() {
+        let reachable = if cfg.reachable[block] {
+            "reachable"
+        } else {
+            "unreachable"
+        };
+        println!("Exit ({}): {}", reachable, describe_block(block));
+    }
+    for (block, basic_block) in cfg.blocks.iter().enumerate() {
+        if let BlockSuccessor::OutOfBounds(target) = basic_block.successor {
+            println!(
+                "Jump out of bounds to {}: {}",
+                target,
+                describe_block(block)
+            );
+        }
+    }
+    for block in (0..cfg.blocks.len())
+        .filter(|block| cfg.blocks[*block].successor == BlockSuccessor::Dynamic)
+    {
+        println!("Dynamic jump: {}", describe_block(block));
+    }
+    for repair in cfg.exit_flips(&instructions) {
+        println!(
+            "Exit reachable by flipping {}: {} -> {}",
+            repair.index, repair.original, repair.replacement
+        );
+    }
+
+    Ok(())
+}
+
+// Settings for generating random handheld programs
+struct ProgramGenerator {
+    len: RangeInclusive<usize>,
+    // Each opcode with its relative weight
+    opcodes: Vec<(&'static Opcode, u32)>,
+    // The range of arguments for jmp and nop, which may become a jmp when repaired
+    jump_range: RangeInclusive<i32>,
+    acc_range: RangeInclusive<i32>,
+}
+
+impl Default for ProgramGenerator {
+    fn default() -> ProgramGenerator {
+        ProgramGenerator {
+            len: 1..=200,
+            opcodes: vec![(&NOP, 1), (&ACC, 2), (&JMP, 2)],
+            jump_range: -20..=20,
+            acc_range: -100..=100,
+        }
+    }
+}
+
+impl ProgramGenerator {
+    fn generate(&self, rng: &mut impl Rng) -> Vec<Instruction> {
+        let opcodes = WeightedIndex::new(self.opcodes.iter().map(|(_opcode, weight)| weight))
+            .expect("Invalid opcode weights");
+        (0..rng.gen_range(self.len.clone()))
+            .map(|_| {
+                let opcode = self.opcodes[opcodes.sample(rng)].0;
+                let arg = if *opcode == ACC {
+                    rng.gen_range(self.acc_range.clone())
+                } else {
+                    rng.gen_range(self.jump_range.clone())
+                };
+                Instruction { opcode, arg }
+            })
+            .collect()
+    }
+}
+
+// Checks invariants which must hold for any program: the compiled backend agrees with the
+// interpreter, any detected loop is a loop in the control flow graph, and a reported repair makes
+// the program terminate, while a failed repair means no single flip would.
+fn check_program_invariants(instructions: &[Instruction], step_limit: usize) -> Result<()> {
+    let interpreted = run_program(instructions);
+    let compiled = compile_program(instructions);
+    if compiled.run(None) != interpreted {
+        return Err(anyhow!("Compiled program gave a different result"));
+    }
+    let limited = RunOptions {
+        step_limit: Some(step_limit),
+        ..RunOptions::default()
+    };
+    if compiled.run(Some(step_limit)) != run_program_with(instructions, limited) {
+        return Err(anyhow!(
+            "Compiled program gave a different result with step limit {}",
+            step_limit
+        ));
+    }
+
+    if let ProgramResult::InfiniteLoop(_, cycle) = &interpreted {
+        let cfg = ControlFlowGraph::new(instructions);
+        let in_cfg_loop = cfg.loops().iter().any(|blocks| {
+            let mut pcs = blocks
+                .iter()
+                .flat_map(|block| cfg.blocks[*block].start..cfg.blocks[*block].end)
+                .collect::<Vec<_>>();
+            let mut cycle = cycle.clone();
+            pcs.sort_unstable();
+            cycle.sort_unstable();
+            pcs == cycle
+        });
+        if !in_cfg_loop {
+            return Err(anyhow!("Loop {:?} not found in control flow graph", cycle));
+        }
+    }
+
+    let candidates = repair_candidates(instructions);
+    for repair in &candidates {
+        match run_program(&repair.apply(instructions)) {
+            ProgramResult::Terminated(_) => {}
+            res => {
+                return Err(anyhow!(
+                    "Repair of instruction {} gave {:?}",
+                    repair.index,
+                    res
+                ))
+            }
+        }
+    }
+    let terminates = matches!(interpreted, ProgramResult::Terminated(_));
+    if repair_program(instructions).is_ok() != (!terminates && candidates.len() == 1) {
+        return Err(anyhow!(
+            "Repair gave {:?} with {} candidates",
+            repair_program(instructions),
+            candidates.len()
+        ));
+    }
+    // Flipping any unexecuted instruction of a program which already terminates also works, so
+    // only programs which don't terminate must have exactly the terminating flips as candidates
+    if !terminates {
+        let terminating_flips = (0..instructions.len())
+            .filter(|i| {
+                instructions[*i].opcode.flipped.is_some_and(|flipped| {
+                    let mut modified = instructions.to_vec();
+                    modified[*i].opcode = flipped;
+                    matches!(run_program(&modified), ProgramResult::Terminated(_))
+                })
+            })
+            .collect::<Vec<_>>();
+        let mut indices = candidates
+            .iter()
+            .map(|repair| repair.index)
+            .collect::<Vec<_>>();
+        indices.sort_unstable();
+        if indices != terminating_flips {
+            return Err(anyhow!(
+                "Repair candidates {:?} differ from terminating flips {:?}",
+                indices,
+                terminating_flips
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+fn fuzz_programs(generator: &ProgramGenerator, seed: u64, iterations: usize) -> Result<()> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    for iteration in 0..iterations {
+        let program = generator.generate(&mut rng);
+        let step_limit = rng.gen_range(0..=program.len());
+        check_program_invariants(&program, step_limit).with_context(|| {
+            format!(
+                "Invariant failed on iteration {} of seed {} for program:\n{}",
+                iteration,
+                seed,
+                disassemble(&program)
+            )
+        })?;
+    }
+
+    Ok(())
+}
+
+fn day_8_fuzz(seed: u64, iterations: usize) -> Result<()> {
+    fuzz_programs(&ProgramGenerator::default(), seed, iterations)?;
+    println!("Day 8, fuzzed {} programs with seed {}", iterations, seed);
+
+    Ok(())
+}
+
+// Times the interpreter against the compiled backend, running the day 8 program and each of its
+// single instruction flips
+fn day_8_bench(iterations: usize) -> Result<()> {
+    let instructions = load_program("res/day_8_input", OPCODES)?;
+    let programs = std::iter::once(instructions.clone())
+        .chain((0..instructions.len()).filter_map(|i| {
+            let flipped = instructions[i].opcode.flipped?;
+            let mut modified = instructions.clone();
+            modified[i].opcode = flipped;
+            Some(modified)
+        }))
+        .collect::<Vec<_>>();
+
+    let start = std::time::Instant::now();
+    let mut interpreted = Vec::new();
+    for _ in 0..iterations {
+        interpreted = programs
+            .iter()
+            .map(|program| run_program(program))
+            .collect::<Vec<_>>();
+    }
+    let interpreted_time = start.elapsed();
+
+    // Each program is compiled inside the timing, as when fuzzing a program is only run once
+    let start = std::time::Instant::now();
+    let mut compiled = Vec::new();
+    for _ in 0..iterations {
+        compiled = programs
+            .iter()
+            .map(|program| compile_program(program).run(None))
+            .collect::<Vec<_>>();
+    }
+    let compiled_time = start.elapsed();
+
+    if interpreted != compiled {
+        return Err(anyhow!("Compiled programs gave different results"));
+    }
+
+    println!(
+        "Day 8, {} programs x {} iterations",
+        programs.len(),
+        iterations
+    );
+    println!("  interpreted:    {:?}", interpreted_time);
+    println!("  compile + run:  {:?}", compiled_time);
+
+    Ok(())
+}
+
+fn parse_xmas_numbers(input: &str) -> Result<Vec<u64>> {
+    input
+        .lines()
+        .map(|line| {
+            line.parse::<u64>()
+                .with_context(|| format!("Error parsing line {:?}", line))
+        })
+        .collect()
+}
+
+// Checks that each number in an XMAS stream is the sum of two of the `window_size` numbers
+// before it. The window is also kept as a multiset, so checking a number takes O(window_size)
+// lookups and sliding the window is O(1).
+struct XmasValidator {
+    window_size: usize,
+    window: VecDeque<u64>,
+    counts: HashMap<u64, usize>,
+    index: usize,
+}
+
+impl XmasValidator {
+    fn new(window_size: usize) -> XmasValidator {
+        XmasValidator {
+            window_size,
+            window: VecDeque::with_capacity(window_size + 1),
+            counts: HashMap::with_capacity(window_size + 1),
+            index: 0,
+        }
+    }
+
+    // Returns whether the next number is valid. Numbers in the preamble are always valid.
+    fn push(&mut self, number: u64) -> bool {
+        let valid = self.window.len() < self.window_size
+            || self.window.iter().filter(|a| **a <= number).any(|a| {
+                let b = number - a;
+                // The pair must be two different entries of the window, even if equal in value
+                let required = if b == *a { 2 } else { 1 };
+                self.counts.get(&b).map_or(0, |count| *count) >= required
+            });
+
+        self.window.push_back(number);
+        *self.counts.entry(number).or_insert(0) += 1;
+        if self.window.len() > self.window_size {
+            let removed = self.window.pop_front().unwrap();
+            match self.counts.get_mut(&removed) {
+                Some(count) if *count > 1 => *count -= 1,
+                _ => {
+                    self.counts.remove(&removed);
+                }
+            }
+        }
+        self.index += 1;
+
+        valid
+    }
+
+    // Returns every invalid number along with its index
+    fn invalid_numbers_in(&mut self, numbers: &[u64]) -> Vec<(usize, u64)> {
+        numbers
+            .iter()
+            .filter_map(|number| {
+                let index = self.index;
+                (!self.push(*number)).then_some((index, *number))
+            })
+            .collect()
+    }
+
+    // Reads numbers from each line, returning every invalid number along with its index
+    fn invalid_numbers(&mut self, reader: impl BufRead) -> Result<Vec<(usize, u64)>> {
+        let mut invalid = Vec::new();
+        for line in reader.lines() {
+            let line = line?;
+            let number = line
+                .parse::<u64>()
+                .with_context(|| format!("Error parsing line {:?}", line))?;
+            let index = self.index;
+            if !self.push(number) {
+                invalid.push((index, number));
+            }
+        }
+
+        Ok(invalid)
+    }
+}
+
+fn day_9_validate(window_size: usize, path: &str) -> Result<()> {
+    let file = std::fs::File::open(path).with_context(|| format!("Error reading {:?}", path))?;
+    let invalid = XmasValidator::new(window_size).invalid_numbers(std::io::BufReader::new(file))?;
+
+    println!(
+        "Day 9, invalid numbers with a window of {}: {}",
+        window_size,
+        invalid.len()
+    );
+    for (index, number) in invalid {
+        println!("  {}: {}", index, number);
+    }
+
+    Ok(())
+}
+
+// Finds the first contiguous range of at least `min_len` numbers which sums to `target_sum`.
+// The window only ever grows at its end and shrinks at its start, so this is O(n).
+fn find_contiguous_sum(numbers: &[u64], target_sum: u64, min_len: usize) -> Option<Range<usize>> {
+    let target_sum = u128::from(target_sum);
+    let mut start = 0;
+    let mut sum: u128 = 0;
+    for (end, number) in numbers.iter().enumerate() {
+        sum += u128::from(*number);
+        while sum > target_sum {
+            sum -= u128::from(numbers[start]);
+            start += 1;
+        }
+        // As the start is as far left as possible, this is the longest range ending here
+        if sum == target_sum && end + 1 - start >= min_len {
+            return Some(start..end + 1);
+        }
+    }
+
+    None
+}
+
+// Finds every contiguous range of at least `min_len` numbers which sums to `target_sum`, ordered
+// by start then end. Ranges may overlap, eg. when extended by zeros.
+fn find_contiguous_sums(numbers: &[u64], target_sum: u64, min_len: usize) -> Vec<Range<usize>> {
+    let target_sum = u128::from(target_sum);
+    // Indices at which each prefix sum occurs
+    let mut prefix_sums: HashMap<u128, Vec<usize>> = HashMap::new();
+    prefix_sums.insert(0, vec![0]);
+    let mut ranges = Vec::new();
+    let mut sum: u128 = 0;
+    for (end, number) in numbers.iter().enumerate() {
+        sum += u128::from(*number);
+        if let Some(starts) = sum
+            .checked_sub(target_sum)
+            .and_then(|prefix| prefix_sums.get(&prefix))
+        {
+            ranges.extend(
+                starts
+                    .iter()
+                    .filter(|start| end + 1 - **start >= min_len)
+                    .map(|start| *start..end + 1),
+            );
+        }
+        prefix_sums.entry(sum).or_default().push(end + 1);
+    }
+
+    ranges.sort_by_key(|range| (range.start, range.end));
+    ranges
+}
+
+fn day_9_weaknesses(window_size: usize, path: &str) -> Result<()> {
+    let input =
+        std::fs::read_to_string(path).with_context(|| format!("Error reading {:?}", path))?;
+    let numbers = parse_xmas_numbers(&input)?;
+
+    let (index, invalid) = *XmasValidator::new(window_size)
+        .invalid_numbers_in(&numbers)
+        .first()
+        .ok_or_else(|| anyhow!("No invalid number found with a window of {}", window_size))?;
+
+    let ranges = find_contiguous_sums(&numbers, invalid, 2);
+    println!(
+        "Day 9, ranges summing to {} (index {}): {}",
+        invalid,
+        index,
+        ranges.len()
+    );
+    for range in ranges {
+        let range_numbers = &numbers[range.clone()];
+        let min = range_numbers.iter().min().unwrap();
+        let max = range_numbers.iter().max().unwrap();
+        println!(
+            "  start {}, length {}: min {}, max {}, weakness {}",
+            range.start,
+            range.len(),
+            min,
+            max,
+            u128::from(*min) + u128::from(*max)
+        );
+    }
+
+    Ok(())
+}
+
+// The original quadratic check of every pair in each window, kept to compare against
+fn invalid_xmas_numbers_pairwise(numbers: &[u64], window_size: usize) -> Vec<(usize, u64)> {
+    (window_size..numbers.len())
+        .filter(|i| {
+            numbers[i - window_size..*i]
+                .iter()
+                .combinations(2)
+                .map(|pair| pair.into_iter().sum::<u64>())
+                .find(|sum| *sum == numbers[*i])
+                .is_none()
+        })
+        .map(|i| (i, numbers[i]))
+        .collect()
+}
+
+fn day_9_bench(iterations: usize) -> Result<()> {
+    let input = std::fs::read_to_string("res/day_9_input")?;
+    let numbers = parse_xmas_numbers(&input)?;
+
+    let start = std::time::Instant::now();
+    let mut pairwise = Vec::new();
+    for _ in 0..iterations {
+        pairwise = invalid_xmas_numbers_pairwise(&numbers, 25);
+    }
+    let pairwise_time = start.elapsed();
+
+    let start = std::time::Instant::now();
+    let mut sliding = Vec::new();
+    for _ in 0..iterations {
+        sliding = XmasValidator::new(25).invalid_numbers_in(&numbers);
+    }
+    let sliding_time = start.elapsed();
+
+    if pairwise != sliding {
+        return Err(anyhow!(
+            "Sliding window gave {:?} but pairwise check gave {:?}",
+            sliding,
+            pairwise
+        ));
+    }
+
+    println!("Day 9, {} iterations", iterations);
+    println!("  pairwise:       {:?}", pairwise_time);
+    println!("  sliding window: {:?}", sliding_time);
+
+    Ok(())
+}
+
+fn day_9() -> Result<()> {
+    let input = std::fs::read_to_string("res/day_9_input")?;
+    let numbers = parse_xmas_numbers(&input)?;
+
+    let (_, invalid) = *XmasValidator::new(25)
+        .invalid_numbers_in(&numbers)
+        .first()
+        .ok_or_else(|| {
+            anyhow!("No number found which doesn't equal sum of two of the previous 25 numbers")
+        })?;
+
+    // 167829540
+    println!("Day 9, part 1: {}", invalid);
+
+    let range = find_contiguous_sum(&numbers, invalid, 2)
+        .ok_or_else(|| anyhow!("Failed to find contiguous range that sums to {}", invalid))?;
+    let range = &numbers[range];
+    let res2 = range.iter().min().unwrap() + range.iter().max().unwrap();
+    // 28045630
+    println!("Day 9, part 2: {}", res2);
+
+    Ok(())
+}
+
+// Counts the ways to get from the first to the last of the sorted `joltages`, where each step
+// increases the joltage by one of `allowed_diffs`
+fn count_arrangements(joltages: &[u64], allowed_diffs: &[u64]) -> Result<u64> {
+    if joltages.is_empty() {
+        return Err(anyhow!("No joltages to arrange"));
+    }
+    let max_gap = allowed_diffs.iter().copied().max().unwrap_or(0);
+
+    // The number of ways to reach each joltage
+    let mut ways = vec![0u64; joltages.len()];
+    ways[0] = 1;
+    for i in 1..joltages.len() {
+        let mut total: u64 = 0;
+        for j in (0..i).rev() {
+            let gap = joltages[i]
+                .checked_sub(joltages[j])
+                .ok_or_else(|| anyhow!("Joltages are not sorted"))?;
+            if gap > max_gap {
+                break;
+            }
+            if allowed_diffs.contains(&gap) {
+                total = total
+                    .checked_add(ways[j])
+                    .ok_or_else(|| anyhow!("Number of arrangements overflowed"))?;
+            }
+        }
+        ways[i] = total;
+    }
+
+    match ways[joltages.len() - 1] {
+        0 => {
+            // The first joltage which can't be reached at all, which is always past the first
+            let to = ways.iter().position(|ways| *ways == 0).unwrap();
+            Err(anyhow!(
+                "No arrangement possible: cannot get from {} to {} jolts with differences of {:?}",
+                joltages[to - 1],
+                joltages[to],
+                allowed_diffs
+            ))
+        }
+        num_ways => Ok(num_ways),
+    }
+}
+
+struct AdapterRules {
+    // Joltage of the charging outlet
+    outlet: u64,
+    // How much higher the device's joltage is than the highest adapter
+    device_offset: u64,
+    // Joltage differences an adapter accepts from the one before it
+    allowed_diffs: Vec<u64>,
+}
+
+impl Default for AdapterRules {
+    fn default() -> AdapterRules {
+        AdapterRules {
+            outlet: 0,
+            device_offset: 3,
+            allowed_diffs: vec![1, 2, 3],
+        }
+    }
+}
+
+impl AdapterRules {
+    // The sorted joltages from the outlet, through every adapter, to the device
+    fn chain(&self, adapters: &[u64]) -> Result<Vec<u64>> {
+        let mut chain = Vec::with_capacity(adapters.len() + 2);
+        chain.push(self.outlet);
+        chain.extend_from_slice(adapters);
+        chain.sort_unstable();
+        if chain[0] != self.outlet {
+            return Err(anyhow!(
+                "Adapter of {} jolts is below the outlet's {} jolts",
+                chain[0],
+                self.outlet
+            ));
+        }
+
+        let device = chain
+            .last()
+            .unwrap()
+            .checked_add(self.device_offset)
+            .ok_or_else(|| anyhow!("Device joltage overflowed"))?;
+        chain.push(device);
+
+        Ok(chain)
+    }
+
+    // Counts each joltage difference when every adapter is used, including any which the rules
+    // don't allow
+    fn difference_histogram(&self, adapters: &[u64]) -> Result<BTreeMap<u64, usize>> {
+        let mut histogram = BTreeMap::new();
+        for (from, to) in self.chain(adapters)?.iter().tuple_windows() {
+            *histogram.entry(to - from).or_insert(0) += 1;
+        }
+
+        Ok(histogram)
+    }
+
+    fn count_arrangements(&self, adapters: &[u64]) -> Result<u64> {
+        count_arrangements(&self.chain(adapters)?, &self.allowed_diffs)
+    }
+}
+
+fn parse_adapters(input: &str) -> Result<Vec<u64>> {
+    input
+        .lines()
+        .map(|line| {
+            line.parse::<u64>()
+                .with_context(|| format!("Error parsing line {:?}", line))
+        })
+        .collect()
+}
+
+fn day_10_rules(rules: &AdapterRules, path: &str) -> Result<()> {
+    let input =
+        std::fs::read_to_string(path).with_context(|| format!("Error reading {:?}", path))?;
+    let adapters = parse_adapters(&input)?;
+
+    println!(
+        "Day 10, outlet {} jolts, device +{} jolts, differences {:?}",
+        rules.outlet, rules.device_offset, rules.allowed_diffs
+    );
+    println!("  differences:");
+    for (diff, count) in rules.difference_histogram(&adapters)? {
+        let allowed = if rules.allowed_diffs.contains(&diff) {
+            ""
+        } else {
+            " (not allowed)"
+        };
+        println!("    {}: {}{}", diff, count, allowed);
+    }
+    match rules.count_arrangements(&adapters) {
+        Ok(num_ways) => println!("  arrangements: {}", num_ways),
+        Err(e) => println!("  arrangements: {}", e),
+    }
+
+    Ok(())
+}
+
+fn day_10() -> Result<()> {
+    let input = std::fs::read_to_string("res/day_10_input")?;
+    let adaptors = parse_adapters(&input)?;
+    let rules = AdapterRules::default();
+
+    let histogram = rules.difference_histogram(&adaptors)?;
+    let res1 = histogram.get(&1).unwrap_or(&0) * histogram.get(&3).unwrap_or(&0);
+    // 1625
+    println!("Day 10, part 1: {}", res1);
+
+    let num_paths = rules.count_arrangements(&adaptors)?;
+
+    // 3100448333024
+    println!("Day 10, part 2: {}", num_paths);
 
     Ok(())
 }
@@ -853,20 +3726,848 @@ fn day_11() -> Result<()> {
 }
 
 fn main() -> Result<()> {
-    if false {
-        day_1()?;
-        day_2()?;
-        day_3()?;
-        day_4()?;
-        day_5()?;
-        day_6()?;
-        day_7()?;
-        day_8()?;
-        day_9()?;
-        day_10()?;
-    }
+    let args = std::env::args().skip(1).collect::<Vec<String>>();
+
+    match args
+        .iter()
+        .map(String::as_str)
+        .collect::<Vec<&str>>()
+        .as_slice()
+    {
+        [] => {
+            if false {
+                day_1()?;
+                day_2()?;
+                day_3()?;
+                day_4()?;
+                day_5()?;
+                day_6()?;
+                day_7()?;
+                day_8()?;
+                day_9()?;
+                day_10()?;
+            }
 
-    day_11()?;
+            day_11()?;
+        }
+        ["day-5", "seat-map"] => day_5_seat_map()?,
+        ["day-6", "quorum", quorum] => day_6_quorum(
+            quorum
+                .parse()
+                .with_context(|| format!("Invalid quorum {:?}", quorum))?,
+        )?,
+        ["day-7", direction @ "containers", bag] | ["day-7", direction @ "contents", bag] => {
+            day_7_reachable(direction, bag)?
+        }
+        ["day-7", "explain", bag] => day_7_explain(bag)?,
+        ["day-7", "paths", from, to] => day_7_paths(from, to)?,
+        ["day-7", "query"] => day_7_query(None)?,
+        ["day-7", "query", query] => day_7_query(Some(query))?,
+        ["day-7", "export", format, options @ ..] => day_7_export(format, options)?,
+        ["day-8", "debug"] => day_8_debug(None)?,
+        ["day-8", "debug", path] => day_8_debug(Some(path))?,
+        ["day-8", "run", path, options @ ..] => day_8_run(path, options)?,
+        ["day-8", "disassemble", path] => day_8_disassemble(path)?,
+        ["day-8", "assemble", path, output] => day_8_assemble(path, output)?,
+        ["day-8", "cfg", path, options @ ..] => day_8_cfg(path, options)?,
+        ["day-8", "bench"] => day_8_bench(100)?,
+        ["day-8", "bench", iterations] => day_8_bench(
+            iterations
+                .parse()
+                .with_context(|| format!("Invalid iteration count {:?}", iterations))?,
+        )?,
+        ["day-8", "fuzz", seed, iterations] => day_8_fuzz(
+            seed.parse()
+                .with_context(|| format!("Invalid seed {:?}", seed))?,
+            iterations
+                .parse()
+                .with_context(|| format!("Invalid iteration count {:?}", iterations))?,
+        )?,
+        ["day-8", "trace", format] => day_8_trace(format, "original")?,
+        ["day-8", "trace", format, program] => day_8_trace(format, program)?,
+        ["day-9", "bench"] => day_9_bench(100)?,
+        ["day-9", "bench", iterations] => day_9_bench(
+            iterations
+                .parse()
+                .with_context(|| format!("Invalid iteration count {:?}", iterations))?,
+        )?,
+        ["day-9", "weaknesses", window_size, path] => day_9_weaknesses(
+            window_size
+                .parse()
+                .with_context(|| format!("Invalid window size {:?}", window_size))?,
+            path,
+        )?,
+        ["day-9", "validate", window_size, path] => day_9_validate(
+            window_size
+                .parse()
+                .with_context(|| format!("Invalid window size {:?}", window_size))?,
+            path,
+        )?,
+        ["day-10", "rules", outlet, device_offset, allowed_diffs, path] => day_10_rules(
+            &AdapterRules {
+                outlet: outlet
+                    .parse()
+                    .with_context(|| format!("Invalid outlet joltage {:?}", outlet))?,
+                device_offset: device_offset
+                    .parse()
+                    .with_context(|| format!("Invalid device offset {:?}", device_offset))?,
+                allowed_diffs: allowed_diffs
+                    .split(',')
+                    .map(|diff| {
+                        diff.parse()
+                            .with_context(|| format!("Invalid joltage difference {:?}", diff))
+                    })
+                    .collect::<Result<Vec<u64>>>()?,
+            },
+            path,
+        )?,
+        _ => return Err(anyhow!("Unrecognized arguments {:?}", args)),
+    }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_seat_errors() {
+        let error = |pass_ids: &[i32]| find_missing_seat_id(pass_ids).unwrap_err().to_string();
+
+        assert_eq!(find_missing_seat_id(&[4, 1, 2]).unwrap(), 3);
+        assert_eq!(error(&[]), "No boarding passes to find a seat between");
+        assert_eq!(error(&[3, 1, 2]), "No free seat between seats 1 and 3");
+        assert_eq!(
+            error(&[1, 3, 5]),
+            "Multiple free seats between seats 1 and 5: 2, 4"
+        );
+        assert_eq!(
+            error(&[1, 3, 5, 7]),
+            "Multiple free seats between seats 1 and 7: 2, 4, ..."
+        );
+        assert_eq!(
+            error(&[1, 2, 4, 2]),
+            "Seat 2 appears on more than one boarding pass"
+        );
+    }
+
+    #[test]
+    fn customs_groups_edge_cases() {
+        let groups = parse_customs_groups("ab\r\nac\r\n\r\nb\rc\r\r  \n\t\n\nz\n\n\n").unwrap();
+        let people = groups
+            .iter()
+            .map(|group| group.people.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            people,
+            vec![vec![0b11, 0b101], vec![0b10, 0b100], vec![1 << 25]]
+        );
+        assert_eq!(groups[0].answered_by_everyone(), 0b1);
+        assert_eq!(groups[1].answered_by_anyone(), 0b110);
+
+        assert!(parse_customs_groups("\n\n  \n").unwrap().is_empty());
+
+        let err = parse_customs_groups("ab\r\n\r\naB\nc1d\n")
+            .unwrap_err()
+            .to_string();
+        assert_eq!(
+            err,
+            "Unexpected answers in customs forms: 'B' at line 3, column 2, '1' at line 4, column 2"
+        );
+    }
+
+    #[test]
+    fn customs_quorum_and_agreement() {
+        let group = &parse_customs_groups("ab\nac\nad\n").unwrap()[0];
+        assert_eq!(group.question_counts()[..5], [3, 1, 1, 1, 0]);
+        assert_eq!(group.answered_by_at_least(0), 0b1111);
+        assert_eq!(group.answered_by_at_least(1), 0b1111);
+        assert_eq!(group.answered_by_at_least(2), 0b1);
+        assert_eq!(group.answered_by_at_least(3), 0b1);
+        assert_eq!(group.answered_by_at_least(4), 0);
+        assert_eq!(group.agreement(), 0.25);
+
+        // Nobody disagrees when nobody answers yes
+        let silent = CustomsGroup { people: vec![0, 0] };
+        assert_eq!(silent.answered_by_at_least(0), 0);
+        assert_eq!(silent.agreement(), 1.0);
+    }
+
+    #[test]
+    fn bag_graph_errors() {
+        let rules = parse_bag_rules(
+            "light red bags contain 1 dark blue bag.
+dark blue bags contain 2 pale green bags.
+pale green bags contain 1 light red bag, 3 dotted black bags.
+dotted black bags contain no other bags.",
+        )
+        .unwrap();
+        let err = BagGraph::new(&rules).err().unwrap().to_string();
+        assert_eq!(
+            err,
+            "Bag rules contain a cycle: dark blue -> pale green -> light red -> dark blue"
+        );
+
+        let rules = parse_bag_rules("light red bags contain 1 dark blue bag.").unwrap();
+        let err = BagGraph::new(&rules).err().unwrap().to_string();
+        assert_eq!(
+            err,
+            "Bag \"light red\" contains \"dark blue\", which has no rule"
+        );
+
+        let rules = parse_bag_rules(
+            "light red bags contain 2 dark blue bags.
+dark blue bags contain no other bags.",
+        )
+        .unwrap();
+        let graph = BagGraph::new(&rules).unwrap();
+        assert_eq!(
+            graph.num_bags_contained("light red").unwrap().to_string(),
+            "2"
+        );
+        for result in &[
+            graph.containers_of("shiny gold").map(|_| ()),
+            graph.contents_of("shiny gold").map(|_| ()),
+            graph.num_bags_contained("shiny gold").map(|_| ()),
+        ] {
+            assert_eq!(
+                result.as_ref().unwrap_err().to_string(),
+                "Unknown bag colour \"shiny gold\""
+            );
+        }
+    }
+
+    #[test]
+    fn bag_depth_and_paths_with_shared_contents() {
+        // Each level holds both bags of the next, so there are 2^60 paths to the bottom
+        let mut input = (0..60)
+            .map(|level| {
+                format!(
+                    "left {0} bags contain 1 left {1} bag, 1 right {1} bag.\n\
+                     right {0} bags contain 1 left {1} bag, 1 right {1} bag.\n",
+                    level,
+                    level + 1
+                )
+            })
+            .collect::<String>();
+        input.push_str("left 60 bags contain 1 shiny gold bag.\n");
+        input.push_str("right 60 bags contain 1 shiny gold bag.\n");
+        input.push_str("shiny gold bags contain no other bags.\n");
+        input.push_str("dotted black bags contain 1 shiny gold bag.\n");
+        let graph = BagGraph::new(&parse_bag_rules(&input).unwrap()).unwrap();
+
+        assert_eq!(graph.max_depth("left 0").unwrap(), 61);
+        assert!(graph.paths("left 0", "dotted black").unwrap().is_empty());
+        assert_eq!(
+            graph.paths("left 59", "shiny gold").unwrap(),
+            vec![
+                vec!["left 59", "left 60", "shiny gold"],
+                vec!["left 59", "right 60", "shiny gold"],
+            ]
+        );
+    }
+
+    #[test]
+    fn bag_count_overflow() {
+        // Each level holds 1000 of the next, so "level 0" holds more than 2^64 bags in total
+        let mut input = String::from("shiny gold bags contain 1 level 0 bag.\n");
+        for level in 0..7 {
+            input.push_str(&format!(
+                "level {} bags contain 1000 level {} bags.\n",
+                level,
+                level + 1
+            ));
+        }
+        input.push_str("level 7 bags contain no other bags.\n");
+        let graph = BagGraph::new(&parse_bag_rules(&input).unwrap()).unwrap();
+
+        let count = graph.num_bags_contained("level 1").unwrap();
+        assert_eq!(count.to_string(), "1001001001001001000");
+        assert_eq!(count.overflow_diagnostic(), None);
+
+        let count = graph.num_bags_contained("shiny gold").unwrap();
+        assert_eq!(count.to_string(), "1001001001001001001001");
+        match &count {
+            BagCount::Big { overflowed, .. } => assert_eq!(overflowed, "level 0"),
+            BagCount::Native(_) => panic!("Expected {} to overflow", count),
+        }
+        assert_eq!(
+            count.overflow_diagnostic().unwrap(),
+            "Count of bags inside \"level 0\" exceeds 64 bits, so was computed with big integers"
+        );
+    }
+
+    #[test]
+    fn bag_query_grammar() {
+        let parse = |input| parse_bag_query(input).map(|(_, query)| query);
+        assert_eq!(
+            parse("contains* 'shiny gold'"),
+            Ok(BagQuery::Containers {
+                bag: "shiny gold",
+                transitive: true
+            })
+        );
+        assert_eq!(
+            parse("  contains   'shiny gold' "),
+            Ok(BagQuery::Containers {
+                bag: "shiny gold",
+                transitive: false
+            })
+        );
+        assert_eq!(
+            parse("contents* 'light red'"),
+            Ok(BagQuery::Contents {
+                bag: "light red",
+                transitive: true
+            })
+        );
+        assert_eq!(
+            parse("contents 'light red'"),
+            Ok(BagQuery::Contents {
+                bag: "light red",
+                transitive: false
+            })
+        );
+        assert_eq!(parse("count 'a'"), Ok(BagQuery::Count("a")));
+        assert_eq!(parse("depth 'a'"), Ok(BagQuery::Depth("a")));
+        assert_eq!(parse("leaves"), Ok(BagQuery::Leaves));
+        assert_eq!(parse("roots"), Ok(BagQuery::Roots));
+        for input in &["path 'a'->'b'", "path 'a' -> 'b'", "path  'a'   ->\t'b'  "] {
+            assert_eq!(parse(input), Ok(BagQuery::Path("a", "b")));
+        }
+
+        for input in &[
+            "contains 'a' extra",
+            "contains *'a'",
+            "contains'a'",
+            "leaves roots",
+            "path 'a' -> 'b' -> 'c'",
+            "path 'a' 'b'",
+            "count a",
+            "",
+        ] {
+            assert!(parse(input).is_err(), "{:?} should not parse", input);
+        }
+    }
+
+    #[test]
+    fn export_broken_bag_rules() {
+        let rules = parse_bag_rules(
+            "light red bags contain 1 dark blue bag, 2 faded plum bags.
+dark blue bags contain 2 pale green bags.
+pale green bags contain 1 dark blue bag.",
+        )
+        .unwrap();
+        let graph = BagGraph::unchecked(&rules);
+        assert_eq!(
+            graph.export(GraphFormat::Dot, None, None).unwrap(),
+            "digraph bags {
+    \"dark blue\";
+    \"faded plum\" [style=dashed];
+    \"light red\";
+    \"pale green\";
+    \"dark blue\" -> \"pale green\" [label=\"2\", color=red];
+    \"light red\" -> \"dark blue\" [label=\"1\"];
+    \"light red\" -> \"faded plum\" [label=\"2\"];
+    \"pale green\" -> \"dark blue\" [label=\"1\", color=red];
+}
+"
+        );
+        assert_eq!(
+            graph
+                .export(GraphFormat::Mermaid, None, Some("faded plum"))
+                .unwrap(),
+            "graph TD
+    bag1[\"faded plum\"]
+    style bag1 stroke-dasharray: 5 5
+    bag2[\"light red\"]
+    bag2 -->|2| bag1
+"
+        );
+
+        let rules = parse_bag_rules("shiny \"gold\" bags contain 1 dark\\#blue bag.").unwrap();
+        let graph = BagGraph::unchecked(&rules);
+        assert_eq!(
+            graph.export(GraphFormat::Dot, None, None).unwrap(),
+            "digraph bags {
+    \"dark\\\\#blue\" [style=dashed];
+    \"shiny \\\"gold\\\"\";
+    \"shiny \\\"gold\\\"\" -> \"dark\\\\#blue\" [label=\"1\"];
+}
+"
+        );
+        assert_eq!(
+            graph.export(GraphFormat::Mermaid, None, None).unwrap(),
+            "graph TD
+    bag0[\"dark\\#35;blue\"]
+    style bag0 stroke-dasharray: 5 5
+    bag1[\"shiny #quot;gold#quot;\"]
+    bag1 -->|1| bag0
+"
+        );
+    }
+
+    const XMAS_EXAMPLE: &str = "35\n20\n15\n25\n47\n40\n62\n55\n65\n95\n102\n117\n150\n182\n127\n219\n299\n277\n309\n576\n";
+
+    #[test]
+    fn xmas_validator_example() {
+        let invalid = XmasValidator::new(5)
+            .invalid_numbers(XMAS_EXAMPLE.as_bytes())
+            .unwrap();
+        assert_eq!(invalid, vec![(14, 127)]);
+    }
+
+    #[test]
+    fn contiguous_sum() {
+        let numbers = XMAS_EXAMPLE
+            .lines()
+            .map(|line| line.parse::<u64>().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(find_contiguous_sum(&numbers, 127, 2), Some(2..6));
+        assert_eq!(find_contiguous_sum(&numbers, 127, 5), None);
+        assert_eq!(find_contiguous_sum(&numbers, 15, 1), Some(2..3));
+        assert_eq!(find_contiguous_sum(&numbers, 15, 2), None);
+        assert_eq!(find_contiguous_sums(&numbers, 127, 2), vec![2..6]);
+        assert_eq!(
+            find_contiguous_sums(&[0, 5, 0, 10, 0], 15, 2),
+            vec![0..4, 0..5, 1..4, 1..5]
+        );
+        assert_eq!(find_contiguous_sum(&[], 15, 2), None);
+        assert_eq!(find_contiguous_sum(&[15], 15, 2), None);
+        assert_eq!(find_contiguous_sum(&[15], 15, 1), Some(0..1));
+        assert_eq!(find_contiguous_sum(&[0, 5, 0, 10], 15, 4), Some(0..4));
+    }
+
+    #[test]
+    fn adapter_arrangements() {
+        let mut small = vec![0, 16, 10, 15, 5, 1, 11, 7, 19, 6, 12, 4, 22];
+        small.sort_unstable();
+        assert_eq!(count_arrangements(&small, &[1, 2, 3]).unwrap(), 8);
+        let mut large = vec![
+            0, 28, 33, 18, 42, 31, 14, 46, 20, 48, 47, 24, 23, 49, 45, 19, 38, 39, 11, 1, 32, 25,
+            35, 8, 17, 7, 9, 4, 2, 34, 10, 3, 52,
+        ];
+        large.sort_unstable();
+        assert_eq!(count_arrangements(&large, &[1, 2, 3]).unwrap(), 19208);
+        assert_eq!(count_arrangements(&[0, 2, 4], &[1, 2]).unwrap(), 1);
+        assert_eq!(
+            count_arrangements(&[0, 1, 2, 3, 4], &[1, 2, 3, 4]).unwrap(),
+            8
+        );
+        assert_eq!(count_arrangements(&[0, 1, 2, 3, 4], &[1, 3]).unwrap(), 3);
+        assert!(count_arrangements(&[0, 1, 5, 8], &[1, 2, 3]).is_err());
+        assert!(count_arrangements(&[0, 2, 4], &[1, 3]).is_err());
+        assert!(count_arrangements(&[], &[1, 2, 3]).is_err());
+    }
+
+    #[test]
+    fn adapter_rules() {
+        let adapters = [16, 10, 15, 5, 1, 11, 7, 19, 6, 12, 4];
+        let rules = AdapterRules::default();
+        let histogram = rules.difference_histogram(&adapters).unwrap();
+        assert_eq!(
+            histogram.into_iter().collect::<Vec<_>>(),
+            vec![(1, 7), (3, 5)]
+        );
+        assert_eq!(rules.count_arrangements(&adapters).unwrap(), 8);
+
+        let rules = AdapterRules {
+            outlet: 2,
+            device_offset: 1,
+            allowed_diffs: vec![1, 2],
+        };
+        let histogram = rules.difference_histogram(&[3, 5, 6]).unwrap();
+        assert_eq!(
+            histogram.into_iter().collect::<Vec<_>>(),
+            vec![(1, 3), (2, 1)]
+        );
+        assert_eq!(rules.count_arrangements(&[3, 5, 6]).unwrap(), 2);
+        assert!(rules.chain(&[1, 3]).is_err());
+
+        // Every gap is within the largest difference, but 2 can't be reached from 0
+        assert_eq!(
+            count_arrangements(&[0, 2, 4], &[1, 3])
+                .unwrap_err()
+                .to_string(),
+            "No arrangement possible: cannot get from 0 to 2 jolts with differences of [1, 3]"
+        );
+        assert_eq!(
+            count_arrangements(&[0, 1, 5], &[1, 3])
+                .unwrap_err()
+                .to_string(),
+            "No arrangement possible: cannot get from 1 to 5 jolts with differences of [1, 3]"
+        );
+    }
+
+    #[test]
+    fn xmas_validator_matches_pairwise() {
+        let input = std::fs::read_to_string("res/day_9_input").unwrap();
+        let numbers = parse_xmas_numbers(&input).unwrap();
+        for window_size in &[2, 5, 25] {
+            let pairwise = invalid_xmas_numbers_pairwise(&numbers, *window_size);
+            assert_eq!(
+                XmasValidator::new(*window_size)
+                    .invalid_numbers(input.as_bytes())
+                    .unwrap(),
+                pairwise
+            );
+            assert_eq!(
+                XmasValidator::new(*window_size).invalid_numbers_in(&numbers),
+                pairwise
+            );
+        }
+    }
+
+    #[test]
+    fn handheld_program_outcomes() {
+        let run = |input: &str, step_limit: Option<usize>| {
+            let program = assemble(input, OPCODES).unwrap();
+            let options = RunOptions {
+                step_limit,
+                ..RunOptions::default()
+            };
+            let result = run_program_with(&program, options);
+            assert_eq!(compile_program(&program).run(step_limit), result);
+            result
+        };
+
+        assert_eq!(
+            run("nop +0\njmp -2\n", None),
+            ProgramResult::Fault(Fault::JumpOutOfBounds { pc: 1, target: -1 })
+        );
+        assert_eq!(
+            run("acc +1\njmp +5\n", None),
+            ProgramResult::Fault(Fault::JumpOutOfBounds { pc: 1, target: 6 })
+        );
+        assert_eq!(
+            run("acc +2\njmp +2\nacc +5\n", None),
+            ProgramResult::Terminated(2)
+        );
+        assert_eq!(
+            run("acc +2147483647\nacc +1\n", None),
+            ProgramResult::Fault(Fault::Overflow { pc: 1 })
+        );
+        assert_eq!(
+            run("acc -2147483648\nnop +0\nacc -1\n", None),
+            ProgramResult::Fault(Fault::Overflow { pc: 2 })
+        );
+
+        let looping = "nop +0\nacc +1\nacc +2\njmp -2\n";
+        assert_eq!(run(looping, Some(3)), ProgramResult::StepLimit(3));
+        assert_eq!(
+            run(looping, Some(4)),
+            ProgramResult::InfiniteLoop(3, vec![1, 2, 3])
+        );
+        assert_eq!(
+            run(looping, None),
+            ProgramResult::InfiniteLoop(3, vec![1, 2, 3])
+        );
+    }
+
+    #[test]
+    fn trace_formats() {
+        let program = assemble("acc +2\njmp +2\nacc -1\nacc -3\n", OPCODES).unwrap();
+        let mut trace = Vec::new();
+        let options = RunOptions {
+            trace: Some(&mut trace),
+            ..RunOptions::default()
+        };
+        assert_eq!(
+            run_program_with(&program, options),
+            ProgramResult::Terminated(-1)
+        );
+
+        assert_eq!(
+            format_trace(&trace, TraceFormat::Jsonl),
+            "{\"step\":0,\"pc\":0,\"instruction\":\"acc +2\",\"acc_before\":0,\"acc_after\":2}
+{\"step\":1,\"pc\":1,\"instruction\":\"jmp +2\",\"acc_before\":2,\"acc_after\":2}
+{\"step\":2,\"pc\":3,\"instruction\":\"acc -3\",\"acc_before\":2,\"acc_after\":-1}
+"
+        );
+        assert_eq!(
+            format_trace(&trace, TraceFormat::Csv),
+            "step,pc,instruction,acc_before,acc_after
+0,0,acc +2,0,2
+1,1,jmp +2,2,2
+2,3,acc -3,2,-1
+"
+        );
+    }
+
+    #[test]
+    fn repair_program_candidates() {
+        let example = assemble(
+            "nop +0\nacc +1\njmp +4\nacc +3\njmp -3\nacc -99\nacc +1\njmp -4\nacc +6\n",
+            OPCODES,
+        )
+        .unwrap();
+        assert_eq!(repair_program(&example).unwrap().index, 7);
+
+        let ambiguous = assemble("nop +2\njmp +0\n", OPCODES).unwrap();
+        assert_eq!(
+            repair_program(&ambiguous).unwrap_err().to_string(),
+            "Multiple flipped instructions make the program terminate: \
+             0 (nop +2 -> jmp +2), 1 (jmp +0 -> nop +0)"
+        );
+
+        let unrepairable = assemble("acc +1\njmp -1\njmp -2\n", OPCODES).unwrap();
+        assert_eq!(
+            repair_program(&unrepairable).unwrap_err().to_string(),
+            "No single flipped instruction makes the program terminate"
+        );
+
+        for (terminating, acc) in [("nop +1\nnop +1\n", 0), ("nop +0\nacc +1\n", 1)] {
+            assert_eq!(
+                repair_program(&assemble(terminating, OPCODES).unwrap())
+                    .unwrap_err()
+                    .to_string(),
+                format!("Program already terminates with acc {}", acc)
+            );
+        }
+
+        // Flipping the loop reaches the end through an overflow, so only the jump past it works
+        let overflowing = assemble("acc +2147483647\nnop +3\njmp +0\nacc +1\n", OPCODES).unwrap();
+        assert_eq!(
+            repair_candidates(&overflowing)
+                .iter()
+                .map(|repair| repair.index)
+                .collect::<Vec<_>>(),
+            vec![1]
+        );
+    }
+
+    #[test]
+    fn control_flow_exit_flips() {
+        let flips = |input: &str| {
+            let program = assemble(input, OPCODES).unwrap();
+            ControlFlowGraph::new(&program)
+                .exit_flips(&program)
+                .iter()
+                .map(|repair| repair.index)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            flips("nop +0\nacc +1\njmp +4\nacc +3\njmp -3\nacc -99\nacc +1\njmp -4\nacc +6\n"),
+            vec![7]
+        );
+        // The flip at 1 would reach the exit, but 1 is never reached
+        assert_eq!(flips("jmp +0\nnop +1\n"), vec![0]);
+        assert_eq!(flips("nop +1\nnop +1\n"), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn assembly_bytecode_round_trip() {
+        let input = "start:
+            acc -7
+            jmp +1
+            nop -2147483648
+            acc +2147483647
+            jmp start
+            jmp +100
+            jmp -2147483648
+            acc -2147483648
+            jmp done
+            nop +2147483647
+            done:
+        ";
+        let program = assemble(input, OPCODES).unwrap();
+        assert_eq!(program[4].arg, -4);
+        assert_eq!(program[8].arg, 2);
+
+        let bytecode = encode_program(&program, OPCODES).unwrap();
+        let decoded = decode_program(&bytecode, OPCODES).unwrap();
+        assert_eq!(decoded, program);
+        assert_eq!(
+            disassemble(&decoded),
+            "L0:
+    acc -7
+    jmp +1
+    nop -2147483648
+    acc +2147483647
+    jmp L0
+    jmp +100
+    jmp -2147483648
+    acc -2147483648
+    jmp end
+    nop +2147483647
+end:
+"
+        );
+        assert_eq!(assemble(&disassemble(&decoded), OPCODES).unwrap(), program);
+
+        // Opcodes are encoded by id rather than by their position in the table
+        let reordered: &[&'static Opcode] = &[&JMP, &ACC, &NOP];
+        assert_eq!(encode_program(&program, reordered).unwrap(), bytecode);
+        assert_eq!(decode_program(&bytecode, reordered).unwrap(), program);
+        assert_eq!(
+            encode_program(&assemble("jmp +1\nacc -1", OPCODES).unwrap(), OPCODES).unwrap(),
+            b"HHBC\x02\x02\x02\x01\x01"
+        );
+        assert!(decode_program(&bytecode, &[&NOP, &ACC]).is_err());
+
+        // Offsets go through the same parser as the puzzle input, which rejects trailing text
+        for invalid in [
+            "nop +0 extra",
+            "acc +2147483648",
+            "acc 5",
+            "jmp +start",
+            "nop",
+        ] {
+            assert!(assemble(invalid, OPCODES).is_err(), "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn debugger_breakpoints() {
+        let program = assemble("nop +0\nacc +1\njmp +1\nacc +2\n", OPCODES).unwrap();
+
+        let mut debugger = Debugger::new(&program, OPCODES);
+        debugger.command("break 0").unwrap();
+        assert_eq!(
+            debugger.command("continue").unwrap(),
+            vec!["Breakpoint at 0", "   0: nop +0"]
+        );
+        debugger.command("break jmp").unwrap();
+        assert_eq!(
+            debugger.command("continue").unwrap(),
+            vec!["Breakpoint at 2", "   2: jmp +1"]
+        );
+        assert_eq!(debugger.command("step").unwrap(), vec!["   3: acc +2"]);
+        assert_eq!(
+            debugger.command("continue").unwrap(),
+            vec!["Program terminated with acc 3"]
+        );
+
+        // A breakpoint stepped onto is reported once, and continuing executes it
+        let mut debugger = Debugger::new(&program, OPCODES);
+        debugger.command("break 1").unwrap();
+        assert_eq!(
+            debugger.command("step").unwrap(),
+            vec!["Breakpoint at 1", "   1: acc +1"]
+        );
+        assert_eq!(
+            debugger.command("continue").unwrap(),
+            vec!["Program terminated with acc 3"]
+        );
+    }
+
+    #[test]
+    fn custom_opcodes() {
+        static SET: Opcode = Opcode {
+            mnemonic: "set",
+            id: 3,
+            execute: |machine, arg| {
+                machine.registers[0] = arg;
+                Ok(())
+            },
+            next_pc: NextPc::Static(|pc, _arg| pc as isize + 1),
+            flipped: None,
+        };
+        static DEC: Opcode = Opcode {
+            mnemonic: "dec",
+            id: 4,
+            execute: |machine, _arg| {
+                machine.registers[0] -= 1;
+                Ok(())
+            },
+            next_pc: NextPc::Static(|pc, _arg| pc as isize + 1),
+            flipped: None,
+        };
+        // Jumps while register 0 isn't zero
+        static JNZ: Opcode = Opcode {
+            mnemonic: "jnz",
+            id: 5,
+            execute: |_machine, _arg| Ok(()),
+            next_pc: NextPc::Dynamic(|machine, arg| match machine.registers[0] {
+                0 => machine.pc as isize + 1,
+                _ => machine.pc as isize + arg as isize,
+            }),
+            flipped: None,
+        };
+        let opcodes: &[&'static Opcode] = &[&NOP, &ACC, &JMP, &SET, &DEC, &JNZ];
+
+        let program = assemble("set +3\nacc +2\ndec +0\njnz -2\n", opcodes).unwrap();
+        assert_eq!(run_program(&program), ProgramResult::Terminated(6));
+        assert_eq!(compile_program(&program).run(None), run_program(&program));
+        assert_eq!(
+            ControlFlowGraph::new(&program)
+                .blocks
+                .last()
+                .unwrap()
+                .successor,
+            BlockSuccessor::Dynamic
+        );
+        assert_eq!(assemble(&disassemble(&program), opcodes).unwrap(), program);
+        let bytecode = encode_program(&program, opcodes).unwrap();
+        assert_eq!(decode_program(&bytecode, opcodes).unwrap(), program);
+        assert!(encode_program(&program, OPCODES).is_err());
+        assert!(assemble("set +3", OPCODES).is_err());
+
+        // Without the decrement, the whole machine state repeats
+        let looping = assemble("set +3\nacc +0\njnz -1\n", opcodes).unwrap();
+        assert_eq!(
+            run_program(&looping),
+            ProgramResult::InfiniteLoop(0, vec![1, 2])
+        );
+        assert_eq!(compile_program(&looping).run(None), run_program(&looping));
+
+        // Every state is new, so the run is stopped before the states kept grow without end
+        let counting = assemble("set +1\nacc +1\njnz -1\n", opcodes).unwrap();
+        let acc = (DYNAMIC_STEP_LIMIT / 2) as i32;
+        assert_eq!(run_program(&counting), ProgramResult::StepLimit(acc));
+        assert_eq!(compile_program(&counting).run(None), run_program(&counting));
+        let mut debugger = Debugger::new(&counting, opcodes);
+        assert_eq!(
+            debugger.command("continue").unwrap(),
+            vec![
+                format!(
+                    "Step limit of {} reached with acc {}",
+                    DYNAMIC_STEP_LIMIT, acc
+                ),
+                "   2: jnz -1".to_string()
+            ]
+        );
+
+        let mut debugger = Debugger::new(&program, opcodes);
+        debugger.command("break jnz").unwrap();
+        assert_eq!(
+            debugger.command("continue").unwrap(),
+            vec!["Breakpoint at 3", "   3: jnz -2"]
+        );
+        debugger.command("delete jnz").unwrap();
+        assert_eq!(
+            debugger.command("continue").unwrap(),
+            vec!["Program terminated with acc 6"]
+        );
+    }
+
+    #[test]
+    fn fuzz_handheld_programs() {
+        fuzz_programs(&ProgramGenerator::default(), 2020, 2000).unwrap();
+    }
+
+    #[test]
+    fn fuzz_handheld_programs_with_long_jumps() {
+        let generator = ProgramGenerator {
+            len: 0..=50,
+            opcodes: vec![(&NOP, 3), (&ACC, 1), (&JMP, 3)],
+            jump_range: -60..=60,
+            ..ProgramGenerator::default()
+        };
+        fuzz_programs(&generator, 8, 2000).unwrap();
+    }
+
+    #[test]
+    fn fuzz_handheld_programs_with_overflowing_acc() {
+        // Most arguments are within a factor of two of i32::MIN or i32::MAX, so the accumulator
+        // overflows in either direction after a few instructions
+        let generator = ProgramGenerator {
+            len: 1..=50,
+            acc_range: i32::MIN..=i32::MAX,
+            ..ProgramGenerator::default()
+        };
+        fuzz_programs(&generator, 2147483647, 2000).unwrap();
+    }
+}